@@ -1,126 +1,514 @@
 #![allow(clippy::new_without_default)]
 
+pub mod channel;
+pub mod debug_view;
+pub mod filter;
+pub mod noise;
+pub mod square;
+pub mod wave;
+
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
+use filter::{AudioFilter, HighPassFilter};
+use noise::Noise;
+use square::Square;
+use wave::Wave;
+
+pub const CLOCK_RATE: u32 = 4_194_304;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+const REGISTER_COUNT: usize = 0x17;
+const READ_MASKS: [u8; REGISTER_COUNT] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10 - NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20 - NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30 - NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40 - NR44
+    0x00, 0x00, 0x70, // NR50 - NR52
+];
+// Samples kept when the frontend does not drain them, one second of stereo.
+const MAX_BUFFERED_SECONDS: usize = 1;
+
 pub struct Apu {
-    master: u8,
-    master_volume: u8,
-    panning: u8,
-    ch1_sweep: u8,
-    ch1_length: u8,
-    ch1_vol: u8,
-    ch1_lo: u8,
-    ch1_hi: u8,
-    ch2_length: u8,
-    ch2_vol: u8,
-    ch2_lo: u8,
-    ch2_hi: u8,
-    ch3_dac: u8,
-    ch3_length: u8,
-    ch3_level: u8,
-    ch3_lo: u8,
-    ch3_hi: u8,
-    ch4_length: u8,
-    ch4_vol: u8,
-    ch4_freq: u8,
-    ch4_ctrl: u8,
-    wave_ram: [u8; 16],
+    pub ch1: Square,
+    pub ch2: Square,
+    pub ch3: Wave,
+    pub ch4: Noise,
+    enabled: bool,
+    registers: [u8; REGISTER_COUNT],
+    frame_step: u8,
+    div_bit: bool,
+    sample_rate: u32,
+    sample_clock: u32,
+    left: f32,
+    right: f32,
+    mixed_cycles: u32,
+    samples: Vec<f32>,
+    record_channels: bool,
+    channel_sums: [f32; 4],
+    channel_samples: Vec<[f32; 4]>,
+    muted: [bool; 4],
+    high_pass: HighPassFilter,
 }
 
 impl Apu {
     pub fn new() -> Apu {
         Apu {
-            master: 0,
-            master_volume: 0,
-            panning: 0,
-            ch1_sweep: 0,
-            ch1_length: 0,
-            ch1_vol: 0,
-            ch1_lo: 0,
-            ch1_hi: 0,
-            ch2_length: 0,
-            ch2_vol: 0,
-            ch2_lo: 0,
-            ch2_hi: 0,
-            ch3_dac: 0,
-            ch3_length: 0,
-            ch3_level: 0,
-            ch3_lo: 0,
-            ch3_hi: 0,
-            ch4_length: 0,
-            ch4_vol: 0,
-            ch4_freq: 0,
-            ch4_ctrl: 0,
-            wave_ram: [0; 16],
+            ch1: Square::new(true),
+            ch2: Square::new(false),
+            ch3: Wave::new(),
+            ch4: Noise::new(),
+            enabled: false,
+            registers: [0; REGISTER_COUNT],
+            frame_step: 0,
+            div_bit: false,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            sample_clock: 0,
+            left: 0.0,
+            right: 0.0,
+            mixed_cycles: 0,
+            samples: vec![],
+            record_channels: false,
+            channel_sums: [0.0; 4],
+            channel_samples: vec![],
+            muted: [false; 4],
+            high_pass: HighPassFilter::new(AudioFilter::Dmg, DEFAULT_SAMPLE_RATE),
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.sample_clock = 0;
+        self.high_pass = HighPassFilter::new(self.high_pass.filter(), sample_rate);
+    }
+
+    pub fn set_filter(&mut self, filter: AudioFilter) {
+        self.high_pass = HighPassFilter::new(filter, self.sample_rate);
+    }
+
+    pub fn filter(&self) -> AudioFilter {
+        self.high_pass.filter()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    // Keeps the output of each channel, before panning and master volume,
+    // next to the mixed samples.
+    pub fn set_record_channels(&mut self, record_channels: bool) {
+        self.record_channels = record_channels;
+        self.channel_sums = [0.0; 4];
+        self.channel_samples.clear();
+    }
+
+    pub fn take_channel_samples(&mut self) -> Vec<[f32; 4]> {
+        std::mem::take(&mut self.channel_samples)
+    }
+
+    pub fn is_muted(&self, channel: usize) -> bool {
+        self.muted[channel]
+    }
+
+    pub fn toggle_mute(&mut self, channel: usize) {
+        self.muted[channel] = !self.muted[channel];
+    }
+
+    // Soloing the only channel playing brings the others back.
+    pub fn toggle_solo(&mut self, channel: usize) {
+        let soloed = self.is_soloed(channel);
+        self.muted = [false; 4];
+        if !soloed {
+            self.muted = [true; 4];
+            self.muted[channel] = false;
+        }
+    }
+
+    pub fn is_soloed(&self, channel: usize) -> bool {
+        (0..4).all(|other| self.muted[other] == (other != channel))
+    }
+
+    // cycles counts clocks at the normal speed of 4 MiHz, DIV is given with
+    // the speed mode because the frame sequencer follows a faster bit in double speed.
+    pub fn tick(&mut self, cycles: u32, div: u16, double_speed: bool) {
+        let mask = if double_speed { 1 << 13 } else { 1 << 12 };
+        let bit = div & mask != 0;
+        if self.enabled && self.div_bit && !bit {
+            self.step_frame_sequencer();
+        }
+        self.div_bit = bit;
+        if self.enabled {
+            self.ch1.step(cycles as u16);
+            self.ch2.step(cycles as u16);
+            self.ch3.step(cycles as u16);
+            self.ch4.step(cycles as u16);
+        }
+        self.mix(cycles);
+    }
+
+    fn step_frame_sequencer(&mut self) {
+        if self.frame_step.is_multiple_of(2) {
+            self.ch1.clock_length();
+            self.ch2.clock_length();
+            self.ch3.clock_length();
+            self.ch4.clock_length();
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.ch1.clock_sweep();
         }
+        if self.frame_step == 7 {
+            self.ch1.envelope.clock();
+            self.ch2.envelope.clock();
+            self.ch4.envelope.clock();
+        }
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    pub fn channel_outputs(&self) -> [u8; 4] {
+        [
+            self.ch1.output(),
+            self.ch2.output(),
+            self.ch3.output(),
+            self.ch4.output(),
+        ]
+    }
+
+    pub fn dacs_on(&self) -> [bool; 4] {
+        [
+            self.ch1.envelope.is_dac_on(),
+            self.ch2.envelope.is_dac_on(),
+            self.ch3.dac,
+            self.ch4.envelope.is_dac_on(),
+        ]
+    }
+
+    // Each DAC turns the digital 0 to 15 into an analog level from 1 to -1, and
+    // outputs nothing while it is off. A channel stopped with its DAC on still
+    // outputs the digital 0, which the high-pass filter brings back to silence.
+    fn mix(&mut self, cycles: u32) {
+        let panning = self.registers[0x15];
+        let volume = self.registers[0x14];
+        let dacs_on = self.dacs_on();
+        let (mut left, mut right) = (0.0, 0.0);
+        for (index, output) in self.channel_outputs().iter().enumerate() {
+            if self.record_channels {
+                self.channel_sums[index] += (*output as u32 * cycles) as f32 / 15.0;
+            }
+            if self.muted[index] || !dacs_on[index] {
+                continue;
+            }
+            let analog = 1.0 - *output as f32 / 7.5;
+            if panning & (0x10 << index) != 0 {
+                left += analog;
+            }
+            if panning & (0x01 << index) != 0 {
+                right += analog;
+            }
+        }
+        left *= ((volume >> 4) & 0b111) as f32 + 1.0;
+        right *= (volume & 0b111) as f32 + 1.0;
+        let scale = cycles as f32 / (4.0 * 8.0);
+        self.left += left * scale;
+        self.right += right * scale;
+        self.mixed_cycles += cycles;
+
+        self.sample_clock += self.sample_rate * cycles;
+        if self.sample_clock < CLOCK_RATE {
+            return;
+        }
+        self.sample_clock -= CLOCK_RATE;
+        let max_samples = self.sample_rate as usize * 2 * MAX_BUFFERED_SECONDS;
+        if self.samples.len() < max_samples {
+            let count = self.mixed_cycles as f32;
+            let dac_on = self.enabled && dacs_on.contains(&true);
+            let (left, right) = self
+                .high_pass
+                .apply(self.left / count, self.right / count, dac_on);
+            self.samples.push(left);
+            self.samples.push(right);
+            if self.record_channels {
+                self.channel_samples
+                    .push(self.channel_sums.map(|sum| sum / count));
+            }
+        }
+        self.channel_sums = [0.0; 4];
+        self.left = 0.0;
+        self.right = 0.0;
+        self.mixed_cycles = 0;
     }
 
     pub fn read(&mut self, loc: u16) -> u8 {
         match loc {
-            0xFF26 => self.master,
-            0xFF25 => self.panning,
-            0xFF24 => self.master_volume,
-
-            0xFF10 => self.ch1_sweep,
-            0xFF11 => self.ch1_length,
-            0xFF12 => self.ch1_vol,
-            0xFF13 => self.ch1_lo,
-            0xFF14 => self.ch1_hi,
-
-            0xFF16 => self.ch2_length,
-            0xFF17 => self.ch2_vol,
-            0xFF18 => self.ch2_lo,
-            0xFF19 => self.ch2_hi,
-
-            0xFF1A => self.ch3_dac,
-            0xFF1B => self.ch3_length,
-            0xFF1C => self.ch3_level,
-            0xFF1D => self.ch3_lo,
-            0xFF1E => self.ch3_hi,
-
-            0xFF30..0xFF3F => self.wave_ram[(loc - 0xFF30) as usize],
-
-            0xFF20 => self.ch4_length,
-            0xFF21 => self.ch4_vol,
-            0xFF22 => self.ch4_freq,
-            0xFF23 => self.ch4_ctrl,
-            _ => {
-                //eprintln!("APU read: not handled: {:x}", loc);
-                0xFF
+            0xFF26 => {
+                let status = self.ch1.enabled as u8
+                    | (self.ch2.enabled as u8) << 1
+                    | (self.ch3.enabled as u8) << 2
+                    | (self.ch4.enabled as u8) << 3;
+                READ_MASKS[0x16] | (self.enabled as u8) << 7 | status
             }
+            0xFF10..=0xFF25 => {
+                let index = (loc - 0xFF10) as usize;
+                self.registers[index] | READ_MASKS[index]
+            }
+            0xFF30..=0xFF3F => self.ch3.read_ram((loc - 0xFF30) as usize),
+            _ => 0xFF,
         }
     }
 
     pub fn write(&mut self, loc: u16, value: u8) {
         match loc {
-            0xFF26 => self.master = value,
-            0xFF25 => self.panning = value,
-            0xFF24 => self.master_volume = value,
-
-            0xFF10 => self.ch1_sweep = value,
-            0xFF11 => self.ch1_length = value,
-            0xFF12 => self.ch1_vol = value,
-            0xFF13 => self.ch1_lo = value,
-            0xFF14 => self.ch1_hi = value,
-
-            0xFF16 => self.ch2_length = value,
-            0xFF17 => self.ch2_vol = value,
-            0xFF18 => self.ch2_lo = value,
-            0xFF19 => self.ch2_hi = value,
-
-            0xFF1A => self.ch3_dac = value,
-            0xFF1B => self.ch3_length = value,
-            0xFF1C => self.ch3_level = value,
-            0xFF1D => self.ch3_lo = value,
-            0xFF1E => self.ch3_hi = value,
-
-            0xFF30..0xFF3F => self.wave_ram[(loc - 0xFF30) as usize] = value,
-
-            0xFF20 => self.ch4_length = value,
-            0xFF21 => self.ch4_vol = value,
-            0xFF22 => self.ch4_freq = value,
-            0xFF23 => self.ch4_ctrl = value,
-            _ => {
-                //eprintln!("APU write: not handled: {:x}", loc);
+            0xFF26 => self.write_power(value),
+            0xFF10..=0xFF25 if self.enabled => self.write_register(loc, value),
+            0xFF30..=0xFF3F => self.ch3.write_ram((loc - 0xFF30) as usize, value),
+            _ => {}
+        }
+    }
+
+    fn write_register(&mut self, loc: u16, value: u8) {
+        self.registers[(loc - 0xFF10) as usize] = value;
+        // Length is clocked on even steps, enabling it before an odd one
+        // gives an extra clock.
+        let extra_clock = self.frame_step % 2 == 1;
+        match loc {
+            0xFF10 => self.ch1.write_sweep(value),
+            0xFF11 => self.ch1.write_length(value),
+            0xFF12 => self.ch1.write_envelope(value),
+            0xFF13 => self.ch1.write_frequency_low(value),
+            0xFF14 => self.ch1.write_control(value, extra_clock),
+
+            0xFF16 => self.ch2.write_length(value),
+            0xFF17 => self.ch2.write_envelope(value),
+            0xFF18 => self.ch2.write_frequency_low(value),
+            0xFF19 => self.ch2.write_control(value, extra_clock),
+
+            0xFF1A => self.ch3.write_dac(value),
+            0xFF1B => self.ch3.length.load(value),
+            0xFF1C => self.ch3.write_level(value),
+            0xFF1D => self.ch3.write_frequency_low(value),
+            0xFF1E => self.ch3.write_control(value, extra_clock),
+
+            0xFF20 => self.ch4.write_length(value),
+            0xFF21 => self.ch4.write_envelope(value),
+            0xFF22 => self.ch4.write_polynomial(value),
+            0xFF23 => self.ch4.write_control(value, extra_clock),
+            _ => {}
+        }
+    }
+
+    fn write_power(&mut self, value: u8) {
+        let enabled = value & 0x80 != 0;
+        if self.enabled && !enabled {
+            for loc in 0xFF10..=0xFF25 {
+                self.write_register(loc, 0);
             }
+            self.ch1.enabled = false;
+            self.ch2.enabled = false;
+            self.ch3.enabled = false;
+            self.ch4.enabled = false;
+        }
+        if !self.enabled && enabled {
+            self.frame_step = 0;
+            self.ch1.reset_position();
+            self.ch2.reset_position();
+        }
+        self.enabled = enabled;
+    }
+}
+
+impl Saveable for Apu {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bytes(&self.registers);
+        self.ch1.write_state(state);
+        self.ch2.write_state(state);
+        self.ch3.write_state(state);
+        self.ch4.write_state(state);
+        state.write_u8(self.frame_step);
+        state.write_bool(self.div_bit);
+        state.write_u32(self.sample_clock);
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        state.read_bytes(&mut self.registers)?;
+        self.ch1.read_state(state)?;
+        self.ch2.read_state(state)?;
+        self.ch3.read_state(state)?;
+        self.ch4.read_state(state)?;
+        self.frame_step = state.read_u8()? & 0b111;
+        self.div_bit = state.read_bool()?;
+        self.sample_clock = state.read_u32()? % CLOCK_RATE;
+        self.samples.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_apu() -> Apu {
+        let mut apu = Apu::new();
+        apu.write(0xFF26, 0x80);
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF25, 0xFF);
+        apu
+    }
+
+    fn clock_frame_sequencer(apu: &mut Apu, steps: usize) {
+        for _ in 0..steps {
+            apu.tick(4, 1 << 12, false);
+            apu.tick(4, 0, false);
+        }
+    }
+
+    #[test]
+    fn it_should_apply_read_masks() {
+        let mut apu = get_apu();
+        apu.write(0xFF11, 0x85);
+        apu.write(0xFF1A, 0x00);
+
+        assert_eq!(apu.read(0xFF10), 0x80);
+        assert_eq!(apu.read(0xFF11), 0xBF);
+        assert_eq!(apu.read(0xFF13), 0xFF);
+        assert_eq!(apu.read(0xFF1A), 0x7F);
+        assert_eq!(apu.read(0xFF24), 0x77);
+        assert_eq!(apu.read(0xFF26), 0xF0);
+        assert_eq!(apu.read(0xFF27), 0xFF);
+    }
+
+    #[test]
+    fn it_should_report_channel_status() {
+        let mut apu = get_apu();
+        apu.write(0xFF17, 0xF0);
+
+        apu.write(0xFF19, 0x80);
+
+        assert_eq!(apu.read(0xFF26), 0xF2);
+    }
+
+    #[test]
+    fn it_should_stop_channel_when_length_expires() {
+        let mut apu = get_apu();
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF11, 62);
+        apu.write(0xFF14, 0xC0);
+
+        clock_frame_sequencer(&mut apu, 2);
+        assert_eq!(apu.read(0xFF26) & 1, 1);
+        clock_frame_sequencer(&mut apu, 2);
+
+        assert_eq!(apu.read(0xFF26) & 1, 0);
+    }
+
+    #[test]
+    fn it_should_ignore_writes_when_powered_off() {
+        let mut apu = get_apu();
+        apu.write(0xFF30, 0x12);
+        apu.write(0xFF26, 0x00);
+
+        apu.write(0xFF24, 0x77);
+
+        assert_eq!(apu.read(0xFF24), 0x00);
+        assert_eq!(apu.read(0xFF25), 0x00);
+        assert_eq!(apu.read(0xFF26), 0x70);
+        assert_eq!(apu.read(0xFF30), 0x12);
+    }
+
+    #[test]
+    fn it_should_produce_stereo_samples_at_sample_rate() {
+        let mut apu = get_apu();
+        apu.set_sample_rate(32_768);
+        apu.write(0xFF25, 0x02);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF16, 0xC0);
+        apu.write(0xFF19, 0x87);
+
+        for _ in 0..CLOCK_RATE / 4 / 64 {
+            apu.tick(4, 0, false);
         }
+        let samples = apu.take_samples();
+
+        assert_eq!(samples.len(), 2 * 32_768 / 64);
+        assert!(samples.iter().step_by(2).all(|left| *left == 0.0));
+        assert!(samples.iter().skip(1).step_by(2).any(|right| *right > 0.0));
+    }
+
+    #[test]
+    fn it_should_pop_when_dac_turns_on() {
+        let mut apu = get_apu();
+        apu.write(0xFF25, 0x22);
+
+        apu.write(0xFF17, 0xF0);
+        for _ in 0..CLOCK_RATE / 4 {
+            apu.tick(4, 0, false);
+        }
+        let samples = apu.take_samples();
+
+        assert!(samples[0] > 0.2);
+        assert!(samples[samples.len() - 1].abs() < 0.001);
+    }
+
+    #[test]
+    fn it_should_keep_dc_offset_without_filter() {
+        let mut apu = get_apu();
+        apu.set_filter(AudioFilter::Disabled);
+        apu.write(0xFF25, 0x22);
+
+        apu.write(0xFF17, 0xF0);
+        for _ in 0..CLOCK_RATE / 4 / 64 {
+            apu.tick(4, 0, false);
+        }
+        let samples = apu.take_samples();
+
+        assert!(samples.iter().all(|sample| *sample == 0.25));
+        apu.write(0xFF17, 0x00);
+        apu.tick(CLOCK_RATE / DEFAULT_SAMPLE_RATE + 1, 0, false);
+        assert_eq!(apu.take_samples(), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn it_should_mute_and_solo_channels() {
+        let mut apu = get_apu();
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF16, 0xC0);
+        apu.write(0xFF19, 0x87);
+
+        apu.toggle_mute(1);
+        for _ in 0..1024 {
+            apu.tick(4, 0, false);
+        }
+        assert!(apu.take_samples().iter().all(|sample| *sample == 0.0));
+        apu.toggle_solo(1);
+        assert!(apu.is_soloed(1));
+        for _ in 0..1024 {
+            apu.tick(4, 0, false);
+        }
+        assert!(apu.take_samples().iter().any(|sample| *sample > 0.0));
+        apu.toggle_solo(1);
+
+        assert!((0..4).all(|channel| !apu.is_muted(channel)));
+    }
+
+    #[test]
+    fn it_should_record_each_channel() {
+        let mut apu = get_apu();
+        apu.set_record_channels(true);
+        apu.write(0xFF25, 0x00);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF11, 0xC0);
+        apu.write(0xFF14, 0x87);
+
+        for _ in 0..CLOCK_RATE / 4 / 64 {
+            apu.tick(4, 0, false);
+        }
+        let samples = apu.take_channel_samples();
+
+        assert_eq!(samples.len(), DEFAULT_SAMPLE_RATE as usize / 64);
+        assert!(samples.iter().any(|sample| sample[0] > 0.0));
+        assert!(samples.iter().all(|sample| sample[1..] == [0.0; 3]));
+        assert!(apu.take_samples().iter().all(|sample| *sample == 0.0));
     }
 }
@@ -0,0 +1,186 @@
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
+
+pub struct LengthCounter {
+    pub enabled: bool,
+    counter: u16,
+    max: u16,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> LengthCounter {
+        LengthCounter {
+            enabled: false,
+            counter: 0,
+            max,
+        }
+    }
+
+    pub fn load(&mut self, value: u8) {
+        self.counter = self.max - (value as u16 & (self.max - 1));
+    }
+
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+        self.counter -= 1;
+        self.counter == 0
+    }
+
+    // The frame sequencer gives an extra clock when length is enabled during a
+    // step that does not clock it. Returns true when the channel must stop.
+    pub fn write_control(&mut self, value: u8, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = value & 0x40 != 0;
+        let mut expired = false;
+        if extra_clock && !was_enabled {
+            expired = self.clock();
+        }
+        if value & 0x80 != 0 && self.counter == 0 {
+            self.counter = self.max;
+            if extra_clock && self.enabled {
+                self.counter -= 1;
+            }
+        }
+        expired && value & 0x80 == 0
+    }
+}
+
+impl Saveable for LengthCounter {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u16(self.counter);
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.counter = state.read_u16()?;
+        Ok(())
+    }
+}
+
+pub struct Envelope {
+    pub volume: u8,
+    initial: u8,
+    increase: bool,
+    period: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            volume: 0,
+            initial: 0,
+            increase: false,
+            period: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.initial = value >> 4;
+        self.increase = value & 0b1000 != 0;
+        self.period = value & 0b111;
+    }
+
+    pub fn is_dac_on(&self) -> bool {
+        self.initial != 0 || self.increase
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = get_period(self.period);
+    }
+
+    pub fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer != 0 {
+            return;
+        }
+        self.timer = get_period(self.period);
+        if self.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+impl Saveable for Envelope {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&[
+            self.volume,
+            self.initial,
+            self.increase as u8,
+            self.period,
+            self.timer,
+        ]);
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut bytes = [0; 5];
+        state.read_bytes(&mut bytes)?;
+        let [volume, initial, increase, period, timer] = bytes;
+        self.volume = volume;
+        self.initial = initial;
+        self.increase = increase != 0;
+        self.period = period;
+        self.timer = timer;
+        Ok(())
+    }
+}
+
+// A period of 0 is treated as 8 by the envelope and sweep timers.
+pub fn get_period(period: u8) -> u8 {
+    if period == 0 {
+        8
+    } else {
+        period
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_expire_length_counter() {
+        let mut length = LengthCounter::new(64);
+        length.load(62);
+        length.enabled = true;
+
+        assert!(!length.clock());
+        assert!(length.clock());
+        assert!(!length.clock());
+    }
+
+    #[test]
+    fn it_should_reload_length_on_trigger() {
+        let mut length = LengthCounter::new(256);
+
+        length.write_control(0xC0, true);
+
+        assert_eq!(length.counter, 255);
+    }
+
+    #[test]
+    fn it_should_step_envelope_volume() {
+        let mut envelope = Envelope::new();
+        envelope.write(0xF1);
+        envelope.trigger();
+
+        envelope.clock();
+        assert_eq!(envelope.volume, 14);
+        envelope.write(0x0A);
+        envelope.trigger();
+        envelope.clock();
+        envelope.clock();
+
+        assert_eq!(envelope.volume, 1);
+        assert!(envelope.is_dac_on());
+    }
+}
@@ -0,0 +1,273 @@
+#![allow(clippy::new_without_default)]
+
+use std::collections::VecDeque;
+
+use crate::apu::{Apu, CLOCK_RATE};
+
+pub const AUDIO_DEBUG_WIDTH: usize = 256;
+pub const AUDIO_DEBUG_HEIGHT: usize = 240;
+
+const SCOPE_WIDTH: usize = 160;
+const SCOPE_HEIGHT: usize = 40;
+const ROW_HEIGHT: usize = 48;
+const TEXT_X: usize = SCOPE_WIDTH + 8;
+const LINE_HEIGHT: usize = 7;
+const HISTORY_SIZE: usize = SCOPE_WIDTH * 8;
+const WAVE_Y: usize = ROW_HEIGHT * 4;
+const BAR_WIDTH: usize = 5;
+
+const CHANNEL_COLORS: [u32; 4] = [0xE0_5050, 0x50_C050, 0x50_90E0, 0xE0_C050];
+const MUTED_COLOR: u32 = 0x60_6060;
+const SCOPE_COLOR: u32 = 0x20_2020;
+const TEXT_COLOR: u32 = 0xFF_FFFF;
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+// 3x5 glyphs, one row of 3 bits per byte, the left pixel in bit 2.
+const GLYPHS: [(char, [u8; 5]); 36] = [
+    ('0', [7, 5, 5, 5, 7]),
+    ('1', [2, 6, 2, 2, 7]),
+    ('2', [7, 1, 7, 4, 7]),
+    ('3', [7, 1, 7, 1, 7]),
+    ('4', [5, 5, 7, 1, 1]),
+    ('5', [7, 4, 7, 1, 7]),
+    ('6', [7, 4, 7, 5, 7]),
+    ('7', [7, 1, 1, 1, 1]),
+    ('8', [7, 5, 7, 5, 7]),
+    ('9', [7, 5, 7, 1, 7]),
+    ('A', [2, 5, 7, 5, 5]),
+    ('B', [6, 5, 6, 5, 6]),
+    ('C', [3, 4, 4, 4, 3]),
+    ('D', [6, 5, 5, 5, 6]),
+    ('E', [7, 4, 6, 4, 7]),
+    ('F', [7, 4, 6, 4, 4]),
+    ('G', [3, 4, 5, 5, 3]),
+    ('H', [5, 5, 7, 5, 5]),
+    ('I', [7, 2, 2, 2, 7]),
+    ('L', [4, 4, 4, 4, 7]),
+    ('M', [5, 7, 7, 5, 5]),
+    ('N', [6, 5, 5, 5, 5]),
+    ('O', [2, 5, 5, 5, 2]),
+    ('R', [6, 5, 6, 5, 5]),
+    ('S', [3, 4, 2, 1, 6]),
+    ('T', [7, 2, 2, 2, 2]),
+    ('U', [5, 5, 5, 5, 7]),
+    ('V', [5, 5, 5, 5, 2]),
+    ('W', [5, 5, 7, 7, 5]),
+    ('Y', [5, 5, 2, 2, 2]),
+    ('Z', [7, 1, 2, 4, 7]),
+    ('#', [5, 7, 5, 7, 5]),
+    ('-', [0, 0, 7, 0, 0]),
+    ('/', [1, 1, 2, 4, 4]),
+    ('.', [0, 0, 0, 0, 2]),
+    (' ', [0, 0, 0, 0, 0]),
+];
+
+// Oscilloscopes of the four channels fed with their recorded samples, next to
+// the note, volume and state of each channel, and the wave RAM below.
+pub struct AudioDebugView {
+    history: [VecDeque<f32>; 4],
+    buffer: Vec<u32>,
+}
+
+impl AudioDebugView {
+    pub fn new() -> AudioDebugView {
+        AudioDebugView {
+            history: std::array::from_fn(|_| VecDeque::from(vec![0.0; HISTORY_SIZE])),
+            buffer: vec![0; AUDIO_DEBUG_WIDTH * AUDIO_DEBUG_HEIGHT],
+        }
+    }
+
+    pub fn push(&mut self, samples: &[[f32; 4]]) {
+        for sample in samples {
+            for (history, value) in self.history.iter_mut().zip(sample) {
+                history.pop_front();
+                history.push_back(*value);
+            }
+        }
+    }
+
+    pub fn render(&mut self, apu: &Apu) -> &[u32] {
+        self.buffer.fill(0);
+        for (channel, channel_color) in CHANNEL_COLORS.iter().enumerate() {
+            let color = match apu.is_muted(channel) {
+                true => MUTED_COLOR,
+                false => *channel_color,
+            };
+            self.draw_scope(channel, color);
+            let y = channel * ROW_HEIGHT;
+            for (line, text) in get_channel_lines(apu, channel).iter().enumerate() {
+                self.draw_text(text, TEXT_X, y + line * LINE_HEIGHT, TEXT_COLOR);
+            }
+        }
+        self.draw_wave_ram(&apu.ch3.wave_ram);
+        &self.buffer
+    }
+
+    fn draw_scope(&mut self, channel: usize, color: u32) {
+        let top = channel * ROW_HEIGHT;
+        self.fill_rect(0, top, SCOPE_WIDTH, SCOPE_HEIGHT, SCOPE_COLOR);
+        let history = &self.history[channel];
+        let start = get_trigger(history);
+        let to_y = |value: f32| {
+            top + SCOPE_HEIGHT - 1 - (value.clamp(0.0, 1.0) * (SCOPE_HEIGHT - 1) as f32) as usize
+        };
+        let mut previous = to_y(history[start]);
+        for x in 0..SCOPE_WIDTH {
+            let y = to_y(history[start + x]);
+            for line in previous.min(y)..=previous.max(y) {
+                self.buffer[line * AUDIO_DEBUG_WIDTH + x] = color;
+            }
+            previous = y;
+        }
+    }
+
+    fn draw_wave_ram(&mut self, wave_ram: &[u8]) {
+        self.draw_text("WAVE RAM", 0, WAVE_Y, TEXT_COLOR);
+        let bottom = WAVE_Y + 8 + 15 * 2;
+        for (index, byte) in wave_ram.iter().enumerate() {
+            for (half, nibble) in [byte >> 4, byte & 0xF].iter().enumerate() {
+                let x = (index * 2 + half) * BAR_WIDTH;
+                let height = *nibble as usize * 2 + 1;
+                self.fill_rect(x, bottom - height, BAR_WIDTH - 1, height, CHANNEL_COLORS[2]);
+            }
+        }
+        let hex: String = wave_ram
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        self.draw_text(&hex, 0, bottom + 3, TEXT_COLOR);
+    }
+
+    fn draw_text(&mut self, text: &str, x: usize, y: usize, color: u32) {
+        for (index, character) in text.chars().enumerate() {
+            let Some((_, rows)) = GLYPHS.iter().find(|(glyph, _)| *glyph == character) else {
+                continue;
+            };
+            for (row, bits) in rows.iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) != 0 {
+                        let px = x + index * 4 + column;
+                        self.buffer[(y + row) * AUDIO_DEBUG_WIDTH + px] = color;
+                    }
+                }
+            }
+        }
+    }
+
+    fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        for row in y..y + height {
+            let start = row * AUDIO_DEBUG_WIDTH + x;
+            self.buffer[start..start + width].fill(color);
+        }
+    }
+}
+
+// Starts the trace on the latest rising edge that leaves a full screen of
+// samples, so a steady note stays still.
+fn get_trigger(history: &VecDeque<f32>) -> usize {
+    let last = history.len() - SCOPE_WIDTH;
+    let (low, high) = history
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(low, high), value| {
+            (low.min(*value), high.max(*value))
+        });
+    let middle = (low + high) / 2.0;
+    (1..=last)
+        .rev()
+        .find(|index| history[index - 1] < middle && history[*index] >= middle)
+        .unwrap_or(last)
+}
+
+fn get_channel_lines(apu: &Apu, channel: usize) -> Vec<String> {
+    let (enabled, frequency, volume) = match channel {
+        0 | 1 => {
+            let square = if channel == 0 { &apu.ch1 } else { &apu.ch2 };
+            let frequency = 131_072.0 / (2048 - square.frequency()) as f32;
+            let volume = format!("VOL {}", square.envelope.volume);
+            (square.enabled, frequency, volume)
+        }
+        2 => {
+            let frequency = 65_536.0 / (2048 - apu.ch3.frequency()) as f32;
+            let volume = ["VOL 0", "VOL 1", "VOL 1/2", "VOL 1/4"][apu.ch3.level() as usize];
+            (apu.ch3.enabled, frequency, String::from(volume))
+        }
+        _ => {
+            let frequency = CLOCK_RATE as f32 / apu.ch4.get_timer_period() as f32;
+            let volume = format!("VOL {}", apu.ch4.envelope.volume);
+            (apu.ch4.enabled, frequency, volume)
+        }
+    };
+    let state = if apu.is_soloed(channel) {
+        "SOLO"
+    } else if apu.is_muted(channel) {
+        "MUTE"
+    } else if !enabled {
+        "OFF"
+    } else {
+        ""
+    };
+    let note = match channel {
+        3 => String::from("NOISE"),
+        _ => get_note_name(frequency),
+    };
+    vec![
+        format!("CH{} {}", channel + 1, note),
+        format!("{:.0} HZ", frequency),
+        volume,
+        String::from(state),
+    ]
+}
+
+// Nearest note of the equal temperament, A4 being 440 Hz.
+pub fn get_note_name(frequency: f32) -> String {
+    let semitones = (12.0 * (frequency / 440.0).log2()).round() as i32 + 57;
+    if semitones < 0 {
+        return String::from("-");
+    }
+    format!("{}{}", NOTE_NAMES[semitones as usize % 12], semitones / 12)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_name_notes() {
+        assert_eq!(get_note_name(440.0), "A4");
+        assert_eq!(get_note_name(261.63), "C4");
+        assert_eq!(get_note_name(1_046.5), "C6");
+        assert_eq!(get_note_name(77.78), "D#2");
+    }
+
+    #[test]
+    fn it_should_trigger_on_rising_edge() {
+        let mut view = AudioDebugView::new();
+        let square: Vec<[f32; 4]> = (0..HISTORY_SIZE)
+            .map(|index| [((index / 50) % 2) as f32; 4])
+            .collect();
+
+        view.push(&square);
+        let start = get_trigger(&view.history[0]);
+
+        assert_eq!(view.history[0][start - 1], 0.0);
+        assert_eq!(view.history[0][start], 1.0);
+        assert!(start + SCOPE_WIDTH <= HISTORY_SIZE);
+    }
+
+    #[test]
+    fn it_should_render_channel_state() {
+        let mut apu = Apu::new();
+        apu.write(0xFF26, 0x80);
+        apu.ch3.wave_ram[0] = 0xF0;
+        let mut view = AudioDebugView::new();
+
+        let buffer = view.render(&apu);
+
+        let bottom = WAVE_Y + 8 + 15 * 2;
+        assert_eq!(buffer[(bottom - 1) * AUDIO_DEBUG_WIDTH], CHANNEL_COLORS[2]);
+        assert_eq!(buffer[(bottom - 31) * AUDIO_DEBUG_WIDTH], CHANNEL_COLORS[2]);
+        assert_eq!(buffer[(bottom - 31) * AUDIO_DEBUG_WIDTH + BAR_WIDTH], 0);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::apu::CLOCK_RATE;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AudioFilter {
+    Dmg,
+    Cgb,
+    Disabled,
+}
+
+impl AudioFilter {
+    // Part of the capacitor charge kept after each clock, the CGB one lets the
+    // DC offset go faster.
+    fn charge_factor(self) -> f64 {
+        match self {
+            AudioFilter::Dmg => 0.999958,
+            AudioFilter::Cgb => 0.998943,
+            AudioFilter::Disabled => 1.0,
+        }
+    }
+}
+
+// The capacitors on the left and right outputs, removing the DC offset of the
+// DACs. Without a DAC on the capacitors keep their charge and the output is silent.
+pub struct HighPassFilter {
+    filter: AudioFilter,
+    charge: f32,
+    left: f32,
+    right: f32,
+}
+
+impl HighPassFilter {
+    pub fn new(filter: AudioFilter, sample_rate: u32) -> HighPassFilter {
+        HighPassFilter {
+            filter,
+            charge: get_sample_charge(filter, sample_rate),
+            left: 0.0,
+            right: 0.0,
+        }
+    }
+
+    pub fn filter(&self) -> AudioFilter {
+        self.filter
+    }
+
+    pub fn apply(&mut self, left: f32, right: f32, dac_on: bool) -> (f32, f32) {
+        if !dac_on {
+            return (0.0, 0.0);
+        }
+        if self.filter == AudioFilter::Disabled {
+            return (left, right);
+        }
+        let out_left = left - self.left;
+        let out_right = right - self.right;
+        self.left = left - out_left * self.charge;
+        self.right = right - out_right * self.charge;
+        (out_left, out_right)
+    }
+}
+
+fn get_sample_charge(filter: AudioFilter, sample_rate: u32) -> f32 {
+    filter
+        .charge_factor()
+        .powf(CLOCK_RATE as f64 / sample_rate as f64) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_remove_dc_offset() {
+        let mut filter = HighPassFilter::new(AudioFilter::Cgb, 44_100);
+
+        let (first, _) = filter.apply(1.0, 1.0, true);
+        let mut last = first;
+        for _ in 0..4410 {
+            last = filter.apply(1.0, 1.0, true).0;
+        }
+
+        assert_eq!(first, 1.0);
+        assert!(last.abs() < 0.001, "{}", last);
+        assert_eq!(filter.apply(1.0, 1.0, false), (0.0, 0.0));
+    }
+
+    #[test]
+    fn it_should_keep_dc_offset_when_disabled() {
+        let mut filter = HighPassFilter::new(AudioFilter::Disabled, 44_100);
+
+        for _ in 0..4410 {
+            filter.apply(0.5, -0.5, true);
+        }
+
+        assert_eq!(filter.apply(0.5, -0.5, true), (0.5, -0.5));
+    }
+}
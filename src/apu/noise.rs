@@ -0,0 +1,163 @@
+use crate::apu::channel::{Envelope, LengthCounter};
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
+
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+pub struct Noise {
+    pub enabled: bool,
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+    shift: u8,
+    short_mode: bool,
+    divisor: u8,
+    lfsr: u16,
+    timer: u32,
+}
+
+impl Noise {
+    pub fn new() -> Noise {
+        Noise {
+            enabled: false,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            shift: 0,
+            short_mode: false,
+            divisor: 0,
+            lfsr: 0x7FFF,
+            timer: 0,
+        }
+    }
+
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value);
+    }
+
+    pub fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        if !self.envelope.is_dac_on() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_polynomial(&mut self, value: u8) {
+        self.shift = value >> 4;
+        self.short_mode = value & 0b1000 != 0;
+        self.divisor = value & 0b111;
+    }
+
+    pub fn write_control(&mut self, value: u8, extra_clock: bool) {
+        if self.length.write_control(value, extra_clock) {
+            self.enabled = false;
+        }
+        if value & 0x80 != 0 {
+            self.enabled = self.envelope.is_dac_on();
+            self.lfsr = 0x7FFF;
+            self.timer = self.get_timer_period();
+            self.envelope.trigger();
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn step(&mut self, cycles: u16) {
+        let mut cycles = cycles as u32;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.get_timer_period();
+            self.shift_lfsr();
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 1 != 0 {
+            return 0;
+        }
+        self.envelope.volume
+    }
+
+    fn shift_lfsr(&mut self) {
+        let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (bit << 14);
+        if self.short_mode {
+            self.lfsr = (self.lfsr & !(1 << 6)) | (bit << 6);
+        }
+    }
+
+    pub fn get_timer_period(&self) -> u32 {
+        DIVISORS[self.divisor as usize] << self.shift
+    }
+}
+
+impl Saveable for Noise {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        self.length.write_state(state);
+        self.envelope.write_state(state);
+        state.write_bytes(&[self.shift, self.short_mode as u8, self.divisor]);
+        state.write_u16(self.lfsr);
+        state.write_u32(self.timer);
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.length.read_state(state)?;
+        self.envelope.read_state(state)?;
+        let mut bytes = [0; 3];
+        state.read_bytes(&mut bytes)?;
+        let [shift, short_mode, divisor] = bytes;
+        self.shift = shift;
+        self.short_mode = short_mode != 0;
+        self.divisor = divisor;
+        self.lfsr = state.read_u16()?;
+        self.timer = state.read_u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_period_length(polynomial: u8) -> usize {
+        let mut noise = Noise::new();
+        noise.write_polynomial(polynomial);
+        // The upper bits only hold the past output after a few shifts.
+        for _ in 0..15 {
+            noise.shift_lfsr();
+        }
+        let start = noise.lfsr;
+        (1..=0x8000)
+            .find(|_| {
+                noise.shift_lfsr();
+                noise.lfsr == start
+            })
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn it_should_repeat_after_32767_steps() {
+        assert_eq!(get_period_length(0x00), 0x7FFF);
+    }
+
+    #[test]
+    fn it_should_repeat_after_127_steps_in_short_mode() {
+        assert_eq!(get_period_length(0x08), 127);
+    }
+
+    #[test]
+    fn it_should_output_when_bit_0_is_clear() {
+        let mut noise = Noise::new();
+        noise.write_envelope(0xA0);
+        noise.write_control(0x80, false);
+        assert_eq!(noise.output(), 0);
+
+        noise.lfsr = 0x7FFE;
+
+        assert_eq!(noise.output(), 10);
+    }
+}
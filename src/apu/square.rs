@@ -0,0 +1,299 @@
+use crate::apu::channel::{get_period, Envelope, LengthCounter};
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+pub struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    shadow: u16,
+    enabled: bool,
+    negate_used: bool,
+}
+
+impl Sweep {
+    pub fn new() -> Sweep {
+        Sweep {
+            period: 0,
+            negate: false,
+            shift: 0,
+            timer: 0,
+            shadow: 0,
+            enabled: false,
+            negate_used: false,
+        }
+    }
+
+    // Returns false when leaving negate mode after a negated calculation,
+    // which stops the channel.
+    fn write(&mut self, value: u8) -> bool {
+        self.period = (value >> 4) & 0b111;
+        self.negate = value & 0b1000 != 0;
+        self.shift = value & 0b111;
+        let keep = self.negate || !self.negate_used;
+        self.negate_used = false;
+        keep
+    }
+
+    fn calculate(&mut self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.negate {
+            self.negate_used = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+}
+
+pub struct Square {
+    pub enabled: bool,
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+    sweep: Option<Sweep>,
+    duty: u8,
+    position: u8,
+    frequency: u16,
+    timer: u16,
+}
+
+impl Square {
+    pub fn new(with_sweep: bool) -> Square {
+        Square {
+            enabled: false,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            sweep: with_sweep.then(Sweep::new),
+            duty: 0,
+            position: 0,
+            frequency: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn frequency(&self) -> u16 {
+        self.frequency
+    }
+
+    pub fn duty(&self) -> u8 {
+        self.duty
+    }
+
+    pub fn write_sweep(&mut self, value: u8) {
+        if let Some(sweep) = &mut self.sweep {
+            if !sweep.write(value) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn write_length(&mut self, value: u8) {
+        self.duty = value >> 6;
+        self.length.load(value);
+    }
+
+    pub fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        if !self.envelope.is_dac_on() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_frequency_low(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x700) | value as u16;
+    }
+
+    pub fn write_control(&mut self, value: u8, extra_clock: bool) {
+        self.frequency = (self.frequency & 0xFF) | ((value as u16 & 0b111) << 8);
+        if self.length.write_control(value, extra_clock) {
+            self.enabled = false;
+        }
+        if value & 0x80 != 0 {
+            self.trigger();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_on();
+        self.timer = self.get_timer_period();
+        self.envelope.trigger();
+        let frequency = self.frequency;
+        let Some(sweep) = &mut self.sweep else {
+            return;
+        };
+        sweep.shadow = frequency;
+        sweep.timer = get_period(sweep.period);
+        sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+        sweep.negate_used = false;
+        if sweep.shift != 0 && sweep.calculate() > 2047 {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = &mut self.sweep else {
+            return;
+        };
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer != 0 {
+            return;
+        }
+        sweep.timer = get_period(sweep.period);
+        if !sweep.enabled || sweep.period == 0 {
+            return;
+        }
+        let frequency = sweep.calculate();
+        if frequency > 2047 {
+            self.enabled = false;
+        } else if sweep.shift != 0 {
+            sweep.shadow = frequency;
+            self.frequency = frequency;
+            if sweep.calculate() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn step(&mut self, cycles: u16) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.get_timer_period();
+            self.position = (self.position + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        DUTY_PATTERNS[self.duty as usize][self.position as usize] * self.envelope.volume
+    }
+
+    pub fn reset_position(&mut self) {
+        self.position = 0;
+    }
+
+    fn get_timer_period(&self) -> u16 {
+        (2048 - self.frequency) * 4
+    }
+}
+
+impl Saveable for Square {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        self.length.write_state(state);
+        self.envelope.write_state(state);
+        if let Some(sweep) = &self.sweep {
+            state.write_bytes(&[
+                sweep.period,
+                sweep.negate as u8,
+                sweep.shift,
+                sweep.timer,
+                sweep.enabled as u8,
+                sweep.negate_used as u8,
+            ]);
+            state.write_u16(sweep.shadow);
+        }
+        state.write_u8(self.duty);
+        state.write_u8(self.position);
+        state.write_u16(self.frequency);
+        state.write_u16(self.timer);
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.length.read_state(state)?;
+        self.envelope.read_state(state)?;
+        if let Some(sweep) = &mut self.sweep {
+            let mut bytes = [0; 6];
+            state.read_bytes(&mut bytes)?;
+            let [period, negate, shift, timer, enabled, negate_used] = bytes;
+            sweep.period = period;
+            sweep.negate = negate != 0;
+            sweep.shift = shift;
+            sweep.timer = timer;
+            sweep.enabled = enabled != 0;
+            sweep.negate_used = negate_used != 0;
+            sweep.shadow = state.read_u16()?;
+        }
+        self.duty = state.read_u8()?;
+        self.position = state.read_u8()?;
+        self.frequency = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_square() -> Square {
+        let mut square = Square::new(true);
+        square.write_envelope(0xF0);
+        square.write_length(0x80);
+        square.write_frequency_low(0xFF);
+        square
+    }
+
+    #[test]
+    fn it_should_follow_duty_pattern() {
+        let mut square = get_square();
+        square.write_control(0x87, false);
+        let mut outputs = vec![];
+
+        for _ in 0..8 {
+            square.step(4);
+            outputs.push(square.output());
+        }
+
+        assert_eq!(outputs, vec![0, 0, 0, 0, 15, 15, 15, 15]);
+    }
+
+    #[test]
+    fn it_should_sweep_frequency_up() {
+        let mut square = get_square();
+        square.write_sweep(0x12);
+        square.write_frequency_low(0x00);
+        square.write_control(0x84, false);
+
+        square.clock_sweep();
+
+        assert_eq!(square.frequency(), 0x500);
+        assert!(square.enabled);
+    }
+
+    #[test]
+    fn it_should_stop_on_sweep_overflow() {
+        let mut square = get_square();
+        square.write_sweep(0x11);
+        square.write_control(0x87, false);
+
+        assert!(!square.enabled);
+    }
+
+    #[test]
+    fn it_should_stop_when_dac_is_off() {
+        let mut square = get_square();
+        square.write_control(0x87, false);
+
+        square.write_envelope(0x00);
+
+        assert!(!square.enabled);
+    }
+}
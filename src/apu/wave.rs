@@ -0,0 +1,181 @@
+use crate::apu::channel::LengthCounter;
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
+
+pub const WAVE_RAM_SIZE: usize = 16;
+
+pub struct Wave {
+    pub enabled: bool,
+    pub dac: bool,
+    pub length: LengthCounter,
+    pub wave_ram: [u8; WAVE_RAM_SIZE],
+    level: u8,
+    position: u8,
+    sample: u8,
+    frequency: u16,
+    timer: u16,
+}
+
+impl Wave {
+    pub fn new() -> Wave {
+        Wave {
+            enabled: false,
+            dac: false,
+            length: LengthCounter::new(256),
+            wave_ram: [0; WAVE_RAM_SIZE],
+            level: 0,
+            position: 0,
+            sample: 0,
+            frequency: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn frequency(&self) -> u16 {
+        self.frequency
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn write_dac(&mut self, value: u8) {
+        self.dac = value & 0x80 != 0;
+        if !self.dac {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_level(&mut self, value: u8) {
+        self.level = (value >> 5) & 0b11;
+    }
+
+    pub fn write_frequency_low(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x700) | value as u16;
+    }
+
+    pub fn write_control(&mut self, value: u8, extra_clock: bool) {
+        self.frequency = (self.frequency & 0xFF) | ((value as u16 & 0b111) << 8);
+        if self.length.write_control(value, extra_clock) {
+            self.enabled = false;
+        }
+        if value & 0x80 != 0 {
+            self.enabled = self.dac;
+            self.position = 0;
+            self.timer = self.get_timer_period() + 6;
+        }
+    }
+
+    // While the channel plays, the CPU only reaches the byte being read.
+    pub fn read_ram(&self, index: usize) -> u8 {
+        if self.enabled {
+            self.wave_ram[self.position as usize / 2]
+        } else {
+            self.wave_ram[index]
+        }
+    }
+
+    pub fn write_ram(&mut self, index: usize, value: u8) {
+        if self.enabled {
+            self.wave_ram[self.position as usize / 2] = value;
+        } else {
+            self.wave_ram[index] = value;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn step(&mut self, cycles: u16) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.get_timer_period();
+            self.position = (self.position + 1) % 32;
+            let byte = self.wave_ram[self.position as usize / 2];
+            self.sample = if self.position.is_multiple_of(2) {
+                byte >> 4
+            } else {
+                byte & 0xF
+            };
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.level == 0 {
+            return 0;
+        }
+        self.sample >> (self.level - 1)
+    }
+
+    fn get_timer_period(&self) -> u16 {
+        (2048 - self.frequency) * 2
+    }
+}
+
+impl Saveable for Wave {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.dac);
+        self.length.write_state(state);
+        state.write_bytes(&self.wave_ram);
+        state.write_bytes(&[self.level, self.position, self.sample]);
+        state.write_u16(self.frequency);
+        state.write_u16(self.timer);
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.dac = state.read_bool()?;
+        self.length.read_state(state)?;
+        state.read_bytes(&mut self.wave_ram)?;
+        let mut bytes = [0; 3];
+        state.read_bytes(&mut bytes)?;
+        [self.level, self.position, self.sample] = bytes;
+        self.frequency = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_play_wave_ram_nibbles() {
+        let mut wave = Wave::new();
+        wave.wave_ram[0] = 0x12;
+        wave.wave_ram[1] = 0x34;
+        wave.write_dac(0x80);
+        wave.write_level(0x20);
+        wave.write_frequency_low(0xFF);
+        wave.write_control(0x87, false);
+        let mut outputs = vec![];
+
+        wave.step(8);
+        for _ in 0..3 {
+            outputs.push(wave.output());
+            wave.step(2);
+        }
+
+        assert_eq!(outputs, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn it_should_shift_output_with_level() {
+        let mut wave = Wave::new();
+        wave.wave_ram[0] = 0x0F;
+        wave.write_dac(0x80);
+        wave.write_level(0x40);
+        wave.write_frequency_low(0xFF);
+        wave.write_control(0x87, false);
+
+        wave.step(8);
+
+        assert_eq!(wave.output(), 7);
+    }
+}
@@ -0,0 +1,213 @@
+#![allow(clippy::new_without_default)]
+
+// Largest change of the resampling ratio, in parts of the nominal ratio.
+pub const MAX_RATE_DELTA: f64 = 0.005;
+
+// A host output taking interleaved stereo samples at its own rate.
+pub trait AudioSink {
+    fn sample_rate(&self) -> u32;
+    // Frames waiting to be played and the most the sink can hold.
+    fn queued(&self) -> usize;
+    fn capacity(&self) -> usize;
+    fn push(&mut self, samples: &[f32]);
+}
+
+impl<S: AudioSink + ?Sized> AudioSink for Box<S> {
+    fn sample_rate(&self) -> u32 {
+        self.as_ref().sample_rate()
+    }
+
+    fn queued(&self) -> usize {
+        self.as_ref().queued()
+    }
+
+    fn capacity(&self) -> usize {
+        self.as_ref().capacity()
+    }
+
+    fn push(&mut self, samples: &[f32]) {
+        self.as_mut().push(samples)
+    }
+}
+
+// Plays nothing, used when there is no audio device and by the tests,
+// which drain it with consume to stand for the device.
+pub struct NullSink {
+    sample_rate: u32,
+    capacity: usize,
+    queued: usize,
+}
+
+impl NullSink {
+    pub fn new(sample_rate: u32, capacity: usize) -> NullSink {
+        NullSink {
+            sample_rate,
+            capacity,
+            queued: 0,
+        }
+    }
+
+    pub fn consume(&mut self, frames: usize) {
+        self.queued = self.queued.saturating_sub(frames);
+    }
+}
+
+impl AudioSink for NullSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn queued(&self) -> usize {
+        self.queued
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn push(&mut self, samples: &[f32]) {
+        self.queued = (self.queued + samples.len() / 2).min(self.capacity);
+    }
+}
+
+// Linear interpolation between stereo frames, step is the number of input
+// frames per output frame.
+pub struct Resampler {
+    position: f64,
+    previous: [f32; 2],
+}
+
+impl Resampler {
+    pub fn new() -> Resampler {
+        Resampler {
+            position: 0.0,
+            previous: [0.0; 2],
+        }
+    }
+
+    pub fn process(&mut self, input: &[f32], step: f64, output: &mut Vec<f32>) {
+        for frame in input.chunks_exact(2) {
+            while self.position < 1.0 {
+                let position = self.position as f32;
+                for (previous, sample) in self.previous.iter().zip(frame) {
+                    output.push(previous + (sample - previous) * position);
+                }
+                self.position += step;
+            }
+            self.position -= 1.0;
+            self.previous = [frame[0], frame[1]];
+        }
+    }
+}
+
+// Resamples the emulator output to the sink rate. The ratio is nudged to keep
+// the sink half full: a draining buffer gets more samples and a filling one
+// fewer, which avoids crackles without touching the frame pacing.
+pub struct AudioOutput<S: AudioSink> {
+    sink: S,
+    resampler: Resampler,
+    buffer: Vec<f32>,
+}
+
+impl<S: AudioSink> AudioOutput<S> {
+    pub fn new(sink: S) -> AudioOutput<S> {
+        AudioOutput {
+            sink,
+            resampler: Resampler::new(),
+            buffer: vec![],
+        }
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    pub fn push(&mut self, samples: &[f32], sample_rate: u32) {
+        let step = sample_rate as f64 / self.sink.sample_rate() as f64 * self.get_rate_control();
+        self.buffer.clear();
+        self.resampler.process(samples, step, &mut self.buffer);
+        self.sink.push(&self.buffer);
+    }
+
+    fn get_rate_control(&self) -> f64 {
+        let fill = self.sink.queued() as f64 / self.sink.capacity().max(1) as f64;
+        1.0 - (1.0 - 2.0 * fill.min(1.0)) * MAX_RATE_DELTA
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_output(queued: usize) -> AudioOutput<NullSink> {
+        let mut sink = NullSink::new(48_000, 200_000);
+        sink.push(&vec![0.0; queued * 2]);
+        AudioOutput::new(sink)
+    }
+
+    #[test]
+    fn it_should_resample_to_sink_rate() {
+        let mut resampler = Resampler::new();
+        let mut output = vec![];
+
+        resampler.process(&[0.5; 2 * 44_100], 44_100.0 / 48_000.0, &mut output);
+
+        assert!(output.len().abs_diff(2 * 48_000) <= 2);
+        assert_eq!(output[output.len() - 2..], [0.5, 0.5]);
+    }
+
+    #[test]
+    fn it_should_interpolate_between_frames() {
+        let mut resampler = Resampler::new();
+        let mut output = vec![];
+
+        resampler.process(&[1.0, -1.0, 0.0, 0.0], 0.5, &mut output);
+
+        assert_eq!(output, vec![0.0, 0.0, 0.5, -0.5, 1.0, -1.0, 0.5, -0.5]);
+    }
+
+    #[test]
+    fn it_should_push_more_samples_when_buffer_drains() {
+        let mut empty = get_output(0);
+        let mut half = get_output(100_000);
+        let mut filled = get_output(150_000);
+        let samples = vec![0.0; 2 * 48_000];
+
+        empty.push(&samples, 48_000);
+        half.push(&samples, 48_000);
+        filled.push(&samples, 48_000);
+
+        assert!(empty.sink().queued() > 48_200);
+        assert_eq!(half.sink().queued(), 100_000 + 48_000);
+        assert!(filled.sink().queued() < 150_000 + 47_900);
+    }
+
+    #[test]
+    fn it_should_keep_buffer_within_capacity() {
+        let mut output = AudioOutput::new(NullSink::new(48_000, 4_800));
+
+        output.push(&vec![0.0; 2 * 48_000], 44_100);
+
+        assert_eq!(output.sink().queued(), 4_800);
+    }
+
+    #[test]
+    fn it_should_settle_when_device_runs_faster() {
+        let mut output = AudioOutput::new(NullSink::new(48_000, 4_800));
+        let frame = vec![0.0; 2 * 735];
+
+        // The device plays 0.25% faster than the emulator produces, the ratio
+        // settles with a quarter of the buffer filled instead of running dry.
+        for _ in 0..6_000 {
+            output.push(&frame, 44_100);
+            output.sink_mut().consume(802);
+        }
+
+        let queued = output.sink().queued();
+        assert!((1_000..=1_400).contains(&queued), "{}", queued);
+    }
+}
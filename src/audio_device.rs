@@ -0,0 +1,98 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use game_boy::audio::AudioSink;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+// Frames kept in the queue, a tenth of a second.
+const LATENCY_DIVISOR: usize = 10;
+
+pub struct DeviceSink {
+    _stream: Stream,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    sample_rate: u32,
+    capacity: usize,
+}
+
+impl DeviceSink {
+    pub fn open() -> Result<DeviceSink, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("no audio output device")?;
+        let supported = device
+            .default_output_config()
+            .map_err(|err| err.to_string())?;
+        let sample_rate = supported.sample_rate().0;
+        let capacity = sample_rate as usize / LATENCY_DIVISOR;
+        let queue = Arc::new(Mutex::new(VecDeque::with_capacity(capacity * 2)));
+        let config = supported.config();
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, queue.clone()),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, queue.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, queue.clone()),
+            format => return Err(format!("unsupported sample format {}", format)),
+        }?;
+        stream.play().map_err(|err| err.to_string())?;
+        Ok(DeviceSink {
+            _stream: stream,
+            queue,
+            sample_rate,
+            capacity,
+        })
+    }
+}
+
+impl AudioSink for DeviceSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn queued(&self) -> usize {
+        self.queue.lock().unwrap().len() / 2
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn push(&mut self, samples: &[f32]) {
+        let mut queue = self.queue.lock().unwrap();
+        let free = (self.capacity * 2).saturating_sub(queue.len());
+        queue.extend(&samples[..samples.len().min(free) & !1]);
+    }
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+) -> Result<Stream, String>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let mut last = [0.0; 2];
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let mut queue = queue.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    // On an underrun the last frame is held to avoid a click.
+                    if queue.len() >= 2 {
+                        last = [queue.pop_front().unwrap(), queue.pop_front().unwrap()];
+                    }
+                    for (channel, sample) in frame.iter_mut().enumerate() {
+                        let value = match channel {
+                            0 | 1 if channels > 1 => last[channel],
+                            _ => (last[0] + last[1]) / 2.0,
+                        };
+                        *sample = T::from_sample(value);
+                    }
+                }
+            },
+            |err| eprintln!("Audio error: {}", err),
+            None,
+        )
+        .map_err(|err| err.to_string())
+}
@@ -1,85 +1,157 @@
 #![allow(clippy::new_without_default)]
 #![allow(unused_variables)]
 
-use std::{env, fs::File, io::Read};
+use std::fs;
 
 use crate::cartridge::header::Header;
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
 
 const TOTAL_ROM_SIZE: u16 = 0x7FFF + 1;
+const ROM_BANK_SIZE: usize = 0x4000;
+pub const BOOTROM_SIZE: usize = 0x100;
+pub const DEFAULT_BOOTROM: &str = "./rom/dmg_boot.bin";
 
 pub mod header;
 
 pub struct Cartridge {
-    rom: [u8; TOTAL_ROM_SIZE as usize],
-    bootrom: [u8; 0x100],
+    rom: Vec<u8>,
+    rom_bank: usize,
+    bank_switching: bool,
+    bootrom: [u8; BOOTROM_SIZE],
+    ram: Vec<u8>,
     header: Header,
+    checksum: u32,
     pub bootstrap: bool,
 }
 
 impl Cartridge {
-    pub fn new() -> Cartridge {
-        let rom = get_rom();
-        let retval = Cartridge {
-            rom,
-            header: Header::new(rom),
-            bootrom: get_bootrom(),
-            bootstrap: true,
-        };
-        println!("{}", retval.header);
-        retval
+    pub fn new(data: &[u8], bootrom: Option<[u8; BOOTROM_SIZE]>) -> Cartridge {
+        let mut rom = [0; TOTAL_ROM_SIZE as usize];
+        let len = data.len().min(rom.len());
+        rom[..len].copy_from_slice(&data[..len]);
+        let header = Header::new(rom);
+        Cartridge {
+            rom: rom.to_vec(),
+            rom_bank: 1,
+            bank_switching: false,
+            ram: vec![0; header.ram_size()],
+            header,
+            checksum: get_checksum(&rom),
+            bootrom: bootrom.unwrap_or([0; BOOTROM_SIZE]),
+            bootstrap: bootrom.is_some(),
+        }
+    }
+
+    // A plain image of 16KB banks, the bank mapped at 0x4000 is selected by
+    // writing its number in 0x2000-0x3FFF. This is what GBS rips expect.
+    pub fn with_rom_banks(image: &[u8], ram_size: usize) -> Cartridge {
+        let mut cartridge = Cartridge::new(image, None);
+        let banks = image.len().div_ceil(ROM_BANK_SIZE).max(2);
+        cartridge.rom = image.to_vec();
+        cartridge.rom.resize(banks * ROM_BANK_SIZE, 0);
+        cartridge.checksum = get_checksum(&cartridge.rom);
+        cartridge.bank_switching = true;
+        cartridge.ram = vec![0; ram_size];
+        cartridge
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
     }
 
     pub fn read(&mut self, at: u16) -> u8 {
         if self.bootstrap {
             match at {
                 0..0x100 => self.bootrom[at as usize],
-                _ => self.rom[at as usize],
+                _ => self.read_rom(at),
             }
         } else {
-            self.rom[at as usize]
+            self.read_rom(at)
+        }
+    }
+
+    fn read_rom(&self, at: u16) -> u8 {
+        match at as usize {
+            at if at < ROM_BANK_SIZE || !self.bank_switching => self.rom[at],
+            at => self.rom[(self.rom_bank * ROM_BANK_SIZE + at - ROM_BANK_SIZE) % self.rom.len()],
+        }
+    }
+    pub fn read_ram(&mut self, at: u16) -> u8 {
+        if self.ram.is_empty() {
+            return 0xFF;
+        }
+        let len = self.ram.len();
+        self.ram[(at as usize - 0xA000) % len]
+    }
+
+    pub fn write_ram(&mut self, at: u16, value: u8) {
+        if self.ram.is_empty() {
+            return;
         }
+        let len = self.ram.len();
+        self.ram[(at as usize - 0xA000) % len] = value;
     }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn load_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
     pub fn write(&mut self, at: u16, value: u8) {
         match at {
+            0x2000..=0x3FFF if self.bank_switching => self.rom_bank = (value as usize).max(1),
             _ => {}
         }
     }
 }
 
-fn get_rom() -> [u8; TOTAL_ROM_SIZE as usize] {
-    let filename = get_filename();
-    if filename == "error" {
-        return [0; TOTAL_ROM_SIZE as usize];
+impl Saveable for Cartridge {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.bootstrap);
+        state.write_u16(self.rom_bank as u16);
+        state.write_u32(self.ram.len() as u32);
+        state.write_bytes(&self.ram);
     }
-    let mut rom = [0; TOTAL_ROM_SIZE as usize];
-    match File::open(filename) {
-        Ok(mut file) => {
-            let _ = file.read(&mut rom).unwrap();
-            rom
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.bootstrap = state.read_bool()?;
+        self.rom_bank = state.read_u16()? as usize;
+        if state.read_u32()? as usize != self.ram.len() {
+            return Err(StateError::InvalidValue("cartridge ram size"));
         }
-        Err(_) => rom,
+        state.read_bytes(&mut self.ram)
     }
 }
 
-fn get_filename() -> String {
-    let arg: Vec<String> = env::args().collect();
-    if arg.len() != 2 {
-        return String::from("error");
-    }
-    String::from(&arg[1])
+pub fn get_checksum(rom: &[u8]) -> u32 {
+    rom.iter().fold(0x811C_9DC5, |hash: u32, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
 }
 
-fn get_bootrom() -> [u8; 0x100 as usize] {
-    let filename = "./rom/dmg_boot.bin";
-    if filename == "error" {
-        return [0; 0x100 as usize];
-    }
-    let mut rom = [0; 0x100 as usize];
-    match File::open(filename) {
-        Ok(mut file) => {
-            let _ = file.read(&mut rom).unwrap();
-            rom
-        }
-        Err(_) => rom,
+pub fn load_rom(filename: &str) -> Result<Vec<u8>, String> {
+    let rom = fs::read(filename).map_err(|err| format!("cannot read rom {}: {}", filename, err))?;
+    if rom.len() < 0x150 {
+        return Err(format!("{} is too small to be a rom", filename));
     }
+    Ok(rom)
+}
+
+pub fn load_bootrom(filename: &str) -> Result<[u8; BOOTROM_SIZE], String> {
+    let data =
+        fs::read(filename).map_err(|err| format!("cannot read boot rom {}: {}", filename, err))?;
+    data.try_into().map_err(|_| {
+        format!(
+            "{} is not a boot rom: expected {} bytes",
+            filename, BOOTROM_SIZE
+        )
+    })
 }
@@ -4,6 +4,7 @@ use std::fmt;
 
 pub struct Header {
     title: String,
+    cgb_flag: u8,
     new_licence_code: [u8; 2],
     sgb: u8,
     cartridge_type: u8,
@@ -20,6 +21,7 @@ impl Header {
     pub fn new(rom: [u8; TOTAL_ROM_SIZE as usize]) -> Header {
         Header {
             title: get_title(rom),
+            cgb_flag: rom[0x143],
             new_licence_code: [rom[0x144], rom[0x145]],
             sgb: rom[0x146] as u8,
             cartridge_type: rom[0x147] as u8,
@@ -34,6 +36,37 @@ impl Header {
     }
 }
 
+impl Header {
+    pub fn title(&self) -> String {
+        let len = if self.supports_cgb() { 15 } else { 16 };
+        let title: String = self.title.chars().take(len).collect();
+        title.trim_end_matches('\0').to_string()
+    }
+
+    pub fn is_nintendo(&self) -> bool {
+        self.old_licence_code == 0x01
+            || (self.old_licence_code == 0x33 && self.new_licence_code == *b"01")
+    }
+
+    pub fn title_checksum(&self) -> u8 {
+        self.title
+            .chars()
+            .fold(0u8, |sum, c| sum.wrapping_add(c as u8))
+    }
+
+    pub fn title_letter(&self, index: usize) -> u8 {
+        self.title.chars().nth(index).map_or(0, |c| c as u8)
+    }
+
+    pub fn supports_cgb(&self) -> bool {
+        self.cgb_flag & 0x80 != 0
+    }
+
+    pub fn ram_size(&self) -> usize {
+        self.ram_size as usize * 1024
+    }
+}
+
 fn get_title(rom: [u8; TOTAL_ROM_SIZE as usize]) -> String {
     let mut retval = String::new();
     for value in rom[0x134..=0x143].iter() {
@@ -0,0 +1,518 @@
+use game_boy::apu::filter::AudioFilter;
+use game_boy::emulator::Model;
+use game_boy::input_script::parse_buttons;
+use game_boy::joypad::{Buttons, DEFAULT_TURBO_RATE, MAX_TURBO_RATE};
+use game_boy::pacing::SyncMode;
+
+const MAX_FAST_FORWARD: u32 = 16;
+
+pub const USAGE: &str = "Usage: game-boy [OPTIONS] ROM
+
+ROM is a game or a .gbs music rip.
+
+Options:
+  -b, --boot-rom PATH   DMG boot rom to run before the game (default: ./rom/dmg_boot.bin if present)
+  -m, --model MODEL     Hardware model: dmg, cgb or auto from the rom header (default: auto)
+      --block-opposing  Release both directions when Left and Right, or Up and Down, are held
+      --boot-buttons KEYS  Buttons held during the cgb boot to pick the colors of a dmg game,
+                        a direction and optionally a or b (e.g. left+b)
+  -s, --scale FACTOR    Window scale factor: 1, 2, 4 or 8 (default: 4)
+  -p, --palette NAME    Color palette: grey, green, pocket, light, contrast or a palette
+                        of the config file (default: grey)
+      --config PATH     Config file with custom palettes and key bindings
+                        (default: ./game-boy.cfg if present)
+      --turbo-rate N    Presses per second of the turbo buttons, from 1 to 30 (default: 10)
+      --fast-forward N  Speed of fast-forward, 2 to 16 times or 0 for as fast as possible
+                        (default: 0)
+      --bind ACTION=KEYS  Bind keys to a button or hotkey, overriding the config file
+                        (e.g. a=Z,Space), can be repeated
+      --headless        Run without opening a window, needs --frames, --cycles or --movie.
+                        Serial output goes to stdout and the final frame hash to stderr
+  -d, --debug           Open the tile debug window
+      --debug-audio     Open the sound debug window with the channel oscilloscopes
+  -t, --trace PATH      Write an instruction trace to PATH
+  -f, --frames N        Stop after N frames
+  -c, --cycles N        Stop after N clock cycles (headless only)
+  -i, --input PATH      Play the scripted input in PATH (headless only)
+      --png PATH        Write the final frame to PATH as a PNG image (headless only)
+      --record-movie PATH  Record the buttons of each frame from power-on to the movie PATH
+      --movie PATH      Replay the movie PATH, headless runs stop at its last frame
+      --verify-movie PATH  Replay the movie PATH headless and check its final frame hash
+      --wav PATH        Record the sound to PATH as a 16-bit stereo WAV file
+      --wav-channels    Also record each sound channel, music.ch1.wav to music.ch4.wav for
+                        --wav music.wav
+      --sync MODE       Pace the frames on the wall clock or on the sound device: video or
+                        audio (default: video)
+      --audio-filter FILTER  High-pass filter of the sound output: dmg, cgb, none or auto
+                        from the model (default: auto)
+      --track N         Song to start a .gbs file with (default: the first song of the file)
+  -h, --help            Print this help
+";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub rom: String,
+    pub boot_rom: Option<String>,
+    pub model: Option<Model>,
+    pub boot_buttons: Buttons,
+    pub block_opposing: bool,
+    pub turbo_rate: u32,
+    pub fast_forward: Option<f64>,
+    pub sync: SyncMode,
+    pub scale: u8,
+    pub palette: String,
+    pub config: Option<String>,
+    pub headless: bool,
+    pub debug: bool,
+    pub debug_audio: bool,
+    pub trace: Option<String>,
+    pub frames: Option<u64>,
+    pub cycles: Option<u64>,
+    pub input: Option<String>,
+    pub png: Option<String>,
+    pub record_movie: Option<String>,
+    pub movie: Option<String>,
+    pub verify_movie: bool,
+    pub wav: Option<String>,
+    pub wav_channels: bool,
+    pub track: Option<u8>,
+    pub audio_filter: Option<AudioFilter>,
+    pub bindings: Vec<(String, String)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(Box<Options>),
+    Help,
+}
+
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: String::new(),
+        boot_rom: None,
+        model: None,
+        boot_buttons: Buttons::default(),
+        block_opposing: false,
+        turbo_rate: DEFAULT_TURBO_RATE,
+        fast_forward: None,
+        sync: SyncMode::Video,
+        scale: 4,
+        palette: String::from("grey"),
+        config: None,
+        headless: false,
+        debug: false,
+        debug_audio: false,
+        trace: None,
+        frames: None,
+        cycles: None,
+        input: None,
+        png: None,
+        record_movie: None,
+        movie: None,
+        verify_movie: false,
+        wav: None,
+        wav_channels: false,
+        track: None,
+        audio_filter: None,
+        bindings: vec![],
+    };
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-b" | "--boot-rom" => options.boot_rom = Some(get_value(arg, iter.next())?),
+            "-m" | "--model" => options.model = parse_model(&get_value(arg, iter.next())?)?,
+            "--boot-buttons" => {
+                options.boot_buttons = parse_buttons(get_value(arg, iter.next())?.split('+'))?
+            }
+            "-s" | "--scale" => options.scale = parse_scale(&get_value(arg, iter.next())?)?,
+            "-p" | "--palette" => options.palette = get_value(arg, iter.next())?,
+            "--config" => options.config = Some(get_value(arg, iter.next())?),
+            "--headless" => options.headless = true,
+            "-d" | "--debug" => options.debug = true,
+            "--debug-audio" => options.debug_audio = true,
+            "-t" | "--trace" => options.trace = Some(get_value(arg, iter.next())?),
+            "-f" | "--frames" => {
+                options.frames = Some(parse_count("frame", &get_value(arg, iter.next())?)?)
+            }
+            "-c" | "--cycles" => {
+                options.cycles = Some(parse_count("cycle", &get_value(arg, iter.next())?)?)
+            }
+            "-i" | "--input" => options.input = Some(get_value(arg, iter.next())?),
+            "--png" => options.png = Some(get_value(arg, iter.next())?),
+            "--record-movie" => options.record_movie = Some(get_value(arg, iter.next())?),
+            "--movie" => options.movie = Some(get_value(arg, iter.next())?),
+            "--verify-movie" => {
+                options.movie = Some(get_value(arg, iter.next())?);
+                options.verify_movie = true;
+                options.headless = true;
+            }
+            "--wav" => options.wav = Some(get_value(arg, iter.next())?),
+            "--wav-channels" => options.wav_channels = true,
+            "--track" => options.track = Some(parse_track(&get_value(arg, iter.next())?)?),
+            "--block-opposing" => options.block_opposing = true,
+            "--turbo-rate" => options.turbo_rate = parse_turbo_rate(&get_value(arg, iter.next())?)?,
+            "--sync" => options.sync = parse_sync(&get_value(arg, iter.next())?)?,
+            "--fast-forward" => {
+                options.fast_forward = parse_fast_forward(&get_value(arg, iter.next())?)?
+            }
+            "--bind" => options
+                .bindings
+                .push(parse_binding(&get_value(arg, iter.next())?)?),
+            "--audio-filter" => {
+                options.audio_filter = parse_audio_filter(&get_value(arg, iter.next())?)?
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => {
+                if rom.is_some() {
+                    return Err(format!("unexpected argument {}", arg));
+                }
+                rom = Some(arg.clone());
+            }
+        }
+    }
+    options.rom = rom.ok_or("missing rom path")?;
+    if options.headless
+        && options.frames.is_none()
+        && options.cycles.is_none()
+        && options.movie.is_none()
+    {
+        return Err(String::from(
+            "--headless needs --frames, --cycles or --movie",
+        ));
+    }
+    if options.movie.is_some() && (options.record_movie.is_some() || options.input.is_some()) {
+        return Err(String::from(
+            "--movie cannot be used with --record-movie or --input",
+        ));
+    }
+    if !options.headless {
+        for (option, value) in [
+            ("--cycles", options.cycles.is_some()),
+            ("--input", options.input.is_some()),
+            ("--png", options.png.is_some()),
+        ] {
+            if value {
+                return Err(format!("{} needs --headless", option));
+            }
+        }
+    }
+    if options.headless && options.wav_channels && options.wav.is_none() {
+        return Err(String::from("--wav-channels needs --wav with --headless"));
+    }
+    if options.headless && (options.debug || options.debug_audio) {
+        return Err(String::from("debug windows cannot be used with --headless"));
+    }
+    Ok(Command::Run(Box::new(options)))
+}
+
+fn get_value(option: &str, value: Option<&String>) -> Result<String, String> {
+    value
+        .cloned()
+        .ok_or_else(|| format!("{} needs a value", option))
+}
+
+fn parse_model(value: &str) -> Result<Option<Model>, String> {
+    match value {
+        "dmg" => Ok(Some(Model::Dmg)),
+        "cgb" => Ok(Some(Model::Cgb)),
+        "auto" => Ok(None),
+        _ => Err(format!("unsupported hardware model {}", value)),
+    }
+}
+
+fn parse_audio_filter(value: &str) -> Result<Option<AudioFilter>, String> {
+    match value {
+        "dmg" => Ok(Some(AudioFilter::Dmg)),
+        "cgb" => Ok(Some(AudioFilter::Cgb)),
+        "none" => Ok(Some(AudioFilter::Disabled)),
+        "auto" => Ok(None),
+        _ => Err(format!("unsupported audio filter {}", value)),
+    }
+}
+
+fn parse_turbo_rate(value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(rate) if (1..=MAX_TURBO_RATE).contains(&rate) => Ok(rate),
+        _ => Err(format!(
+            "invalid turbo rate {}, expected 1 to {}",
+            value, MAX_TURBO_RATE
+        )),
+    }
+}
+
+fn parse_sync(value: &str) -> Result<SyncMode, String> {
+    match value {
+        "video" => Ok(SyncMode::Video),
+        "audio" => Ok(SyncMode::Audio),
+        _ => Err(format!("unsupported sync mode {}", value)),
+    }
+}
+
+fn parse_fast_forward(value: &str) -> Result<Option<f64>, String> {
+    match value.parse::<u32>() {
+        Ok(0) => Ok(None),
+        Ok(speed) if (2..=MAX_FAST_FORWARD).contains(&speed) => Ok(Some(speed as f64)),
+        _ => Err(format!(
+            "invalid fast-forward speed {}, expected 0 or 2 to {}",
+            value, MAX_FAST_FORWARD
+        )),
+    }
+}
+
+fn parse_binding(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((action, keys)) => Ok((action.trim().to_string(), keys.trim().to_string())),
+        None => Err(format!("invalid binding {}, expected ACTION=KEYS", value)),
+    }
+}
+
+fn parse_scale(value: &str) -> Result<u8, String> {
+    match value {
+        "1" | "2" | "4" | "8" => Ok(value.parse().unwrap()),
+        _ => Err(format!("invalid scale {}, expected 1, 2, 4 or 8", value)),
+    }
+}
+
+fn parse_track(value: &str) -> Result<u8, String> {
+    match value.parse() {
+        Ok(track) if track > 0 => Ok(track),
+        _ => Err(format!("invalid track {}", value)),
+    }
+}
+
+fn parse_count(what: &str, value: &str) -> Result<u64, String> {
+    match value.parse() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("invalid {} count {}", what, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| String::from(*value)).collect()
+    }
+
+    #[test]
+    fn it_should_parse_rom_with_defaults() {
+        let command = parse_args(&args(&["tetris.gb"])).unwrap();
+
+        let Command::Run(options) = command else {
+            panic!("expected run command");
+        };
+        assert_eq!(options.rom, "tetris.gb");
+        assert_eq!(options.scale, 4);
+        assert_eq!(options.model, None);
+        assert!(!options.headless);
+    }
+
+    #[test]
+    fn it_should_parse_options() {
+        let command = parse_args(&args(&[
+            "--scale",
+            "2",
+            "-p",
+            "green",
+            "--frames",
+            "60",
+            "--headless",
+            "--trace",
+            "out.txt",
+            "--block-opposing",
+            "--turbo-rate",
+            "20",
+            "--fast-forward",
+            "4",
+            "--sync",
+            "audio",
+            "tetris.gb",
+        ]))
+        .unwrap();
+
+        let Command::Run(options) = command else {
+            panic!("expected run command");
+        };
+        assert_eq!(options.scale, 2);
+        assert_eq!(options.palette, "green");
+        assert_eq!(options.frames, Some(60));
+        assert!(options.headless);
+        assert_eq!(options.trace, Some(String::from("out.txt")));
+        assert!(options.block_opposing);
+        assert_eq!(options.turbo_rate, 20);
+        assert_eq!(options.fast_forward, Some(4.0));
+        assert_eq!(options.sync, SyncMode::Audio);
+    }
+
+    #[test]
+    fn it_should_parse_model() {
+        let command =
+            parse_args(&args(&["-m", "cgb", "--boot-buttons", "left+b", "rom.gb"])).unwrap();
+
+        let Command::Run(options) = command else {
+            panic!("expected run command");
+        };
+        assert_eq!(options.model, Some(Model::Cgb));
+        assert!(options.boot_buttons.left && options.boot_buttons.b);
+    }
+
+    #[test]
+    fn it_should_return_help() {
+        assert_eq!(parse_args(&args(&["rom.gb", "--help"])), Ok(Command::Help));
+    }
+
+    #[test]
+    fn it_should_refuse_missing_rom() {
+        assert!(parse_args(&args(&["--debug"])).is_err());
+    }
+
+    #[test]
+    fn it_should_refuse_two_roms() {
+        assert!(parse_args(&args(&["a.gb", "b.gb"])).is_err());
+    }
+
+    #[test]
+    fn it_should_refuse_invalid_values() {
+        assert!(parse_args(&args(&["rom.gb", "--scale", "3"])).is_err());
+        assert!(parse_args(&args(&["rom.gb", "--model", "gba"])).is_err());
+        assert!(parse_args(&args(&["rom.gb", "--turbo-rate", "60"])).is_err());
+        assert!(parse_args(&args(&["rom.gb", "--fast-forward", "1"])).is_err());
+        assert!(parse_args(&args(&["rom.gb", "--sync", "vsync"])).is_err());
+        assert!(parse_args(&args(&["rom.gb", "--frames", "0"])).is_err());
+        assert!(parse_args(&args(&["rom.gb", "--palette"])).is_err());
+        assert!(parse_args(&args(&["rom.gb", "--unknown"])).is_err());
+    }
+
+    #[test]
+    fn it_should_refuse_headless_without_frames() {
+        assert!(parse_args(&args(&["rom.gb", "--headless"])).is_err());
+    }
+
+    #[test]
+    fn it_should_parse_headless_outputs() {
+        let command = parse_args(&args(&[
+            "--headless",
+            "--cycles",
+            "1000",
+            "--input",
+            "inputs.txt",
+            "--png",
+            "out.png",
+            "rom.gb",
+        ]))
+        .unwrap();
+
+        let Command::Run(options) = command else {
+            panic!("expected run command");
+        };
+        assert_eq!(options.cycles, Some(1000));
+        assert_eq!(options.input, Some(String::from("inputs.txt")));
+        assert_eq!(options.png, Some(String::from("out.png")));
+    }
+
+    #[test]
+    fn it_should_parse_wav_recording() {
+        let command =
+            parse_args(&args(&["--wav", "music.wav", "--wav-channels", "rom.gb"])).unwrap();
+
+        let Command::Run(options) = command else {
+            panic!("expected run command");
+        };
+        assert_eq!(options.wav, Some(String::from("music.wav")));
+        assert!(options.wav_channels);
+        assert!(parse_args(&args(&[
+            "--headless",
+            "-f",
+            "1",
+            "--wav-channels",
+            "rom.gb"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn it_should_parse_track() {
+        let command = parse_args(&args(&["--track", "3", "music.gbs"])).unwrap();
+
+        let Command::Run(options) = command else {
+            panic!("expected run command");
+        };
+        assert_eq!(options.track, Some(3));
+        assert!(parse_args(&args(&["--track", "0", "music.gbs"])).is_err());
+    }
+
+    #[test]
+    fn it_should_parse_audio_filter() {
+        let command = parse_args(&args(&["--audio-filter", "none", "rom.gb"])).unwrap();
+
+        let Command::Run(options) = command else {
+            panic!("expected run command");
+        };
+        assert_eq!(options.audio_filter, Some(AudioFilter::Disabled));
+        assert!(parse_args(&args(&["--audio-filter", "gba", "rom.gb"])).is_err());
+    }
+
+    #[test]
+    fn it_should_parse_bindings() {
+        let command = parse_args(&args(&[
+            "--bind",
+            "a=Z,Space",
+            "--bind",
+            "quit=Q",
+            "rom.gb",
+        ]))
+        .unwrap();
+
+        let Command::Run(options) = command else {
+            panic!("expected run command");
+        };
+        assert_eq!(
+            options.bindings,
+            [
+                (String::from("a"), String::from("Z,Space")),
+                (String::from("quit"), String::from("Q"))
+            ]
+        );
+        assert!(parse_args(&args(&["--bind", "a", "rom.gb"])).is_err());
+    }
+
+    #[test]
+    fn it_should_parse_debug_audio() {
+        let command = parse_args(&args(&["--debug-audio", "music.gbs"])).unwrap();
+
+        let Command::Run(options) = command else {
+            panic!("expected run command");
+        };
+        assert!(options.debug_audio);
+        assert!(parse_args(&args(&["--headless", "-f", "1", "--debug-audio", "rom.gb"])).is_err());
+    }
+
+    #[test]
+    fn it_should_parse_movies() {
+        let command = parse_args(&args(&["--verify-movie", "run.movie", "rom.gb"])).unwrap();
+
+        let Command::Run(options) = command else {
+            panic!("expected run command");
+        };
+        assert_eq!(options.movie, Some(String::from("run.movie")));
+        assert!(options.verify_movie && options.headless);
+        assert!(parse_args(&args(&["--record-movie", "run.movie", "rom.gb"])).is_ok());
+        assert!(parse_args(&args(&[
+            "--movie",
+            "run.movie",
+            "--record-movie",
+            "new.movie",
+            "rom.gb"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn it_should_refuse_headless_options_with_window() {
+        assert!(parse_args(&args(&["rom.gb", "--png", "out.png"])).is_err());
+        assert!(parse_args(&args(&["rom.gb", "--cycles", "10"])).is_err());
+    }
+}
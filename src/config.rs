@@ -0,0 +1,203 @@
+use crate::gamepad::GamepadBindings;
+use crate::keyboard::KeyBindings;
+use game_boy::ppu::color::{parse_palette, DmgPalettes, PRESETS};
+use std::{fs, path::Path};
+
+pub const DEFAULT_CONFIG: &str = "game-boy.cfg";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub palettes: Vec<(String, DmgPalettes)>,
+    pub keys: KeyBindings,
+    pub gamepad: GamepadBindings,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut section = None;
+        for (index, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                section = Some(
+                    config
+                        .start_section(name.trim())
+                        .map_err(|err| format!("line {}: {}", index + 1, err))?,
+                );
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected key = value", index + 1));
+            };
+            let res = match section {
+                Some(Section::Palette(palette)) => {
+                    config.set_palette(palette, key.trim(), value.trim())
+                }
+                Some(Section::Keys) => config.keys.bind(key.trim(), value.trim()),
+                Some(Section::Gamepad) => config.gamepad.bind(key.trim(), value.trim()),
+                None => Err(String::from("expected a section")),
+            };
+            res.map_err(|err| format!("line {}: {}", index + 1, err))?;
+        }
+        Ok(config)
+    }
+
+    pub fn palettes(&self) -> Vec<(String, DmgPalettes)> {
+        PRESETS
+            .iter()
+            .map(|(name, palette)| (name.to_string(), DmgPalettes::uniform(*palette)))
+            .chain(self.palettes.iter().cloned())
+            .collect()
+    }
+
+    fn start_section(&mut self, name: &str) -> Result<Section, String> {
+        match name.split_once(' ') {
+            None if name == "keys" => Ok(Section::Keys),
+            None if name == "gamepad" => Ok(Section::Gamepad),
+            Some(("palette", palette)) => {
+                self.palettes.push((
+                    palette.trim().to_string(),
+                    DmgPalettes::uniform(PRESETS[0].1),
+                ));
+                Ok(Section::Palette(self.palettes.len() - 1))
+            }
+            _ => Err(format!("unknown section {}", name)),
+        }
+    }
+
+    fn set_palette(&mut self, index: usize, key: &str, value: &str) -> Result<(), String> {
+        let palette = parse_palette(value)?;
+        let palettes = &mut self.palettes[index].1;
+        match key {
+            "colors" => *palettes = DmgPalettes::uniform(palette),
+            "bg" => palettes.bg = palette,
+            "obj0" => palettes.obj0 = palette,
+            "obj1" => palettes.obj1 = palette,
+            _ => return Err(format!("unknown palette key {}", key)),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Section {
+    Palette(usize),
+    Keys,
+    Gamepad,
+}
+
+// A # starts a comment, unless it prefixes a #RRGGBB color after the start of
+// the line.
+fn strip_comment(line: &str) -> &str {
+    for (index, _) in line.match_indices('#') {
+        let word = line[index + 1..].split_whitespace().next().unwrap_or("");
+        let is_color = word.len() == 6 && word.chars().all(|c| c.is_ascii_hexdigit());
+        if index == 0 || !is_color || !line[..index].ends_with(char::is_whitespace) {
+            return &line[..index];
+        }
+    }
+    line
+}
+
+pub fn load_config(filename: Option<&str>) -> Result<Config, String> {
+    let filename = match filename {
+        Some(filename) => filename,
+        None if Path::new(DEFAULT_CONFIG).exists() => DEFAULT_CONFIG,
+        None => return Ok(Config::default()),
+    };
+    let text = fs::read_to_string(filename)
+        .map_err(|err| format!("cannot read config {}: {}", filename, err))?;
+    Config::parse(&text).map_err(|err| format!("{}: {}", filename, err))
+}
+
+pub fn find_palette(palettes: &[(String, DmgPalettes)], name: &str) -> Result<usize, String> {
+    palettes
+        .iter()
+        .position(|(palette, _)| palette == name)
+        .ok_or_else(|| format!("unknown palette {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamepad::GamepadButton;
+    use crate::keyboard::Action;
+    use minifb::Key;
+
+    #[test]
+    fn it_should_parse_custom_palettes() {
+        let config = Config::parse(
+            "# colors\n[palette sunset]\ncolors = FFF6D3 F9A875 EB6B6F 7C3F58\n\
+             obj1 = FFFFFF AAAAAA 555555 000000\n",
+        )
+        .unwrap();
+
+        let (name, palettes) = &config.palettes[0];
+        assert_eq!(name, "sunset");
+        assert_eq!(palettes.bg, [0xFFF6D3, 0xF9A875, 0xEB6B6F, 0x7C3F58]);
+        assert_eq!(palettes.obj0, palettes.bg);
+        assert_eq!(palettes.obj1[1], 0xAAAAAA);
+    }
+
+    #[test]
+    fn it_should_parse_prefixed_colors() {
+        let config = Config::parse(
+            "[palette sunset] # warm\ncolors = #FFF6D3 #F9A875 #EB6B6F #7C3F58 # comment\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.palettes[0].1.bg,
+            [0xFFF6D3, 0xF9A875, 0xEB6B6F, 0x7C3F58]
+        );
+    }
+
+    #[test]
+    fn it_should_list_presets_before_custom_palettes() {
+        let config = Config::parse("[palette mine]\nbg = 000000 111111 222222 333333\n").unwrap();
+
+        let palettes = config.palettes();
+
+        assert_eq!(find_palette(&palettes, "grey"), Ok(0));
+        assert_eq!(find_palette(&palettes, "mine"), Ok(PRESETS.len()));
+        assert!(find_palette(&palettes, "unknown").is_err());
+    }
+
+    #[test]
+    fn it_should_parse_key_bindings() {
+        let config = Config::parse("[keys]\na = Z X\nscreenshot = F9\n").unwrap();
+
+        assert_eq!(config.keys.keys(Action::A), [Key::Z, Key::X]);
+        assert_eq!(config.keys.keys(Action::Screenshot), [Key::F9]);
+        assert_eq!(config.keys.keys(Action::B), [Key::K]);
+        assert!(Config::parse("[keys]\njump = Space\n").is_err());
+    }
+
+    #[test]
+    fn it_should_parse_gamepad_bindings() {
+        let config = Config::parse("[gamepad]\na = south\nb = west\n").unwrap();
+
+        assert_eq!(config.gamepad.buttons(Action::A), [GamepadButton::South]);
+        assert_eq!(config.gamepad.buttons(Action::B), [GamepadButton::West]);
+        assert_eq!(
+            config.gamepad.buttons(Action::Start),
+            [GamepadButton::Start]
+        );
+        assert!(Config::parse("[gamepad]\nquit = start\n").is_err());
+    }
+
+    #[test]
+    fn it_should_refuse_invalid_config() {
+        assert!(Config::parse("colors = FFFFFF AAAAAA 555555 000000\n").is_err());
+        assert!(Config::parse("[palette a]\nshade = FFFFFF AAAAAA 555555 000000\n").is_err());
+        assert!(Config::parse("[palette a]\nbg\n").is_err());
+        assert!(Config::parse("[palette a]\nbg = +FFFFF AAAAAA 555555 000000\n").is_err());
+        assert!(Config::parse("[colors]\n").is_err());
+    }
+}
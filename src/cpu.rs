@@ -1,8 +1,11 @@
 #![allow(unused_imports)]
 #![allow(clippy::new_without_default)]
 
+use std::io::Write;
+
 use registers::Reg16;
 
+use crate::cartridge::Cartridge;
 use crate::cpu::execute::Addr;
 use crate::cpu::execute::Addr::{BC, DE, HL};
 use crate::cpu::registers::Reg8;
@@ -11,6 +14,7 @@ use crate::cpu::registers::Registers;
 use crate::debug_tools::handle_debug;
 use crate::joypad::Joypad;
 use crate::memorybus::MemoryBus;
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
 
 pub mod alu;
 pub mod bit_operations;
@@ -25,6 +29,7 @@ pub mod timer;
 pub struct Cpu {
     pub reg: Registers,
     pub memory: MemoryBus,
+    pub trace: Option<Box<dyn Write>>,
     ime: bool,
     prepare_ime: bool,
     halted: bool,
@@ -35,9 +40,35 @@ impl Cpu {
         Cpu {
             reg: Registers::new(),
             memory: MemoryBus::new(),
+            trace: None,
+            prepare_ime: false,
+            ime: false,
+            halted: false,
+        }
+    }
+
+    pub fn with_cartridge(cartridge: Cartridge, cgb: bool) -> Cpu {
+        let mut cpu = Cpu {
+            reg: Registers::new(),
+            memory: MemoryBus::with_cartridge(cartridge, cgb),
+            trace: None,
             prepare_ime: false,
             ime: false,
             halted: false,
+        };
+        if !cpu.memory.is_bootstrap() {
+            cpu.skip_bootrom(cgb);
+        }
+        cpu
+    }
+
+    fn skip_bootrom(&mut self, cgb: bool) {
+        self.memory.pc = 0x100;
+        self.memory.write(0xFF40, 0x91);
+        self.memory.write(0xFF47, 0xFC);
+        if cgb {
+            [self.reg.a, self.reg.f, self.reg.b, self.reg.c] = [0x11, 0x80, 0x00, 0x00];
+            [self.reg.d, self.reg.e, self.reg.h, self.reg.l] = [0xFF, 0x56, 0x00, 0x0D];
         }
     }
 
@@ -83,3 +114,21 @@ impl Cpu {
         self.memory.pc = addr;
     }
 }
+
+impl Saveable for Cpu {
+    fn write_state(&self, state: &mut StateWriter) {
+        self.reg.write_state(state);
+        state.write_bool(self.ime);
+        state.write_bool(self.prepare_ime);
+        state.write_bool(self.halted);
+        self.memory.write_state(state);
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.reg.read_state(state)?;
+        self.ime = state.read_bool()?;
+        self.prepare_ime = state.read_bool()?;
+        self.halted = state.read_bool()?;
+        self.memory.read_state(state)
+    }
+}
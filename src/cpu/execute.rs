@@ -38,7 +38,7 @@ impl Cpu {
     pub fn execute(&mut self, opcode: u8) {
         match opcode {
             0x0 => {}
-            0x10 => {} // Stop is not implemented
+            0x10 => self.stop(),
             0xCB => self.execute_cb(),
             0x76 => self.halt(),
 
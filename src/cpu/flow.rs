@@ -96,6 +96,10 @@ impl Cpu {
         self.memory.write_byte(self.reg.sp, lo);
     }
 
+    pub fn stop(&mut self) {
+        self.memory.switch_speed();
+    }
+
     pub fn halt(&mut self) {
         self.halted = true;
     }
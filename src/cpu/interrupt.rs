@@ -1,3 +1,5 @@
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
+
 pub struct Interrupt {
     pub iflag: u8,
     pub ie: u8,
@@ -12,6 +14,10 @@ impl Interrupt {
         self.iflag |= 0b0001_0000;
     }
 
+    pub fn require_serial(&mut self) {
+        self.iflag |= 0b0000_1000;
+    }
+
     pub fn require_timer(&mut self) {
         self.iflag |= 0b0000_0100;
     }
@@ -52,6 +58,19 @@ impl Interrupt {
     }
 }
 
+impl Saveable for Interrupt {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.iflag);
+        state.write_u8(self.ie);
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.iflag = state.read_u8()?;
+        self.ie = state.read_u8()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -3,6 +3,8 @@
 
 use std::fmt;
 
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
+
 const ZERO: u8 = 0b_1000_0000;
 const N_FLAG: u8 = 0b_0100_0000;
 const HALF_CARRY: u8 = 0b_0010_0000;
@@ -131,6 +133,25 @@ impl Registers {
     }
 }
 
+impl Saveable for Registers {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&[
+            self.a, self.b, self.c, self.d, self.e, self.f, self.h, self.l,
+        ]);
+        state.write_u16(self.sp);
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut values = [0; 8];
+        state.read_bytes(&mut values)?;
+        [
+            self.a, self.b, self.c, self.d, self.e, self.f, self.h, self.l,
+        ] = values;
+        self.sp = state.read_u16()?;
+        Ok(())
+    }
+}
+
 pub fn test_half_carry_8(value: u8, addend: u8, carry: u8) -> bool {
     let mask = 0b_0000_1111;
     ((value as u16 & mask) + (addend as u16 & mask) + carry as u16) > mask
@@ -1,4 +1,5 @@
 use crate::memorybus::MemoryBus;
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
 
 pub const DIV: u16 = 0xFF04;
 pub const TIMA: u16 = 0xFF05;
@@ -10,57 +11,196 @@ pub struct Timer {
     pub tima: u8,
     pub tac: u8,
     pub tma: u8,
+    overflow: bool,
+    reloading: bool,
 }
 impl Timer {
     pub fn new() -> Timer {
         Timer {
             div: 0x8,
             tima: 0,
-            tac: 0,
+            tac: 0xF8,
             tma: 0,
+            overflow: false,
+            reloading: false,
         }
     }
 
     pub fn handle_timer(&mut self) -> bool {
-        self.handle_div() && self.handle_tima()
+        self.reloading = false;
+        let mut interrupt = false;
+        if self.overflow {
+            self.overflow = false;
+            self.reloading = true;
+            self.tima = self.tma;
+            interrupt = true;
+        }
+        self.set_div(self.div.wrapping_add(4));
+        interrupt
+    }
+
+    pub fn write_div(&mut self) {
+        self.set_div(0);
+    }
+
+    pub fn write_tima(&mut self, value: u8) {
+        if self.reloading {
+            return;
+        }
+        self.tima = value;
+        self.overflow = false;
+    }
+
+    pub fn write_tma(&mut self, value: u8) {
+        self.tma = value;
+        if self.reloading {
+            self.tima = value;
+        }
+    }
+
+    pub fn write_tac(&mut self, value: u8) {
+        let before = self.timer_signal();
+        self.tac = value | 0xF8;
+        if before && !self.timer_signal() {
+            self.increment_tima();
+        }
     }
 
-    fn handle_div(&mut self) -> bool {
-        let frequ = self.freq_bit();
-        let mut div = self.div;
-        let before = ((div & frequ) != 0) && self.is_tima_on();
-        div = div.wrapping_add(4);
+    fn set_div(&mut self, div: u16) {
+        let before = self.timer_signal();
         self.div = div;
-        if before && ((div & frequ) == 0) {
-            return true;
+        if before && !self.timer_signal() {
+            self.increment_tima();
         }
-        false
     }
 
-    fn freq_bit(&mut self) -> u16 {
-        let tac = self.tac;
-        match tac & 0b11 {
+    fn timer_signal(&self) -> bool {
+        self.div & self.freq_bit() != 0 && self.is_tima_on()
+    }
+
+    fn freq_bit(&self) -> u16 {
+        match self.tac & 0b11 {
             0b00 => 1 << 9,
             0b01 => 1 << 3,
             0b10 => 1 << 5,
-            0b11 => 1 << 7,
-            _ => panic!("TIMER: Wrong tac access"),
+            _ => 1 << 7,
         }
     }
 
-    fn is_tima_on(&mut self) -> bool {
-        let tac = self.tac;
-        tac >> 2 & 1 == 1
+    fn is_tima_on(&self) -> bool {
+        self.tac >> 2 & 1 == 1
     }
 
-    fn handle_tima(&mut self) -> bool {
+    fn increment_tima(&mut self) {
         let (res, overflow) = self.tima.overflowing_add(1);
+        self.tima = res;
         if overflow {
-            self.tima = self.tma;
-            true
-        } else {
-            self.tima = res;
-            false
+            self.overflow = true;
+        }
+    }
+}
+
+impl Saveable for Timer {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.div);
+        state.write_u8(self.tima);
+        state.write_u8(self.tac);
+        state.write_u8(self.tma);
+        state.write_bool(self.overflow);
+        state.write_bool(self.reloading);
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.div = state.read_u16()?;
+        self.tima = state.read_u8()?;
+        self.tac = state.read_u8()?;
+        self.tma = state.read_u8()?;
+        self.overflow = state.read_bool()?;
+        self.reloading = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_running_timer(tima: u8) -> Timer {
+        let mut timer = Timer::new();
+        timer.div = 0;
+        timer.tima = tima;
+        timer.write_tac(0b101);
+        timer
+    }
+
+    #[test]
+    fn it_should_increment_every_16_cycles_at_262khz() {
+        let mut timer = get_running_timer(0);
+
+        for _ in 0..8 {
+            timer.handle_timer();
+        }
+
+        assert_eq!(timer.tima, 2);
+    }
+
+    #[test]
+    fn it_should_delay_reload_after_overflow() {
+        let mut timer = get_running_timer(0xFF);
+        timer.tma = 0x42;
+        for _ in 0..4 {
+            assert!(!timer.handle_timer());
+        }
+
+        assert_eq!(timer.tima, 0);
+        assert!(timer.handle_timer());
+        assert_eq!(timer.tima, 0x42);
+    }
+
+    #[test]
+    fn it_should_cancel_reload_when_tima_is_written_before() {
+        let mut timer = get_running_timer(0xFF);
+        for _ in 0..4 {
+            timer.handle_timer();
+        }
+
+        timer.write_tima(0x10);
+
+        assert!(!timer.handle_timer());
+        assert_eq!(timer.tima, 0x10);
+    }
+
+    #[test]
+    fn it_should_ignore_tima_write_during_reload() {
+        let mut timer = get_running_timer(0xFF);
+        timer.tma = 0x42;
+        for _ in 0..5 {
+            timer.handle_timer();
         }
+
+        timer.write_tima(0x10);
+        timer.write_tma(0x20);
+
+        assert_eq!(timer.tima, 0x20);
+    }
+
+    #[test]
+    fn it_should_increment_on_div_write_falling_edge() {
+        let mut timer = get_running_timer(0);
+        timer.div = 0b1000;
+
+        timer.write_div();
+
+        assert_eq!(timer.tima, 1);
+    }
+
+    #[test]
+    fn it_should_increment_when_disabled_on_high_bit() {
+        let mut timer = get_running_timer(0);
+        timer.div = 0b1000;
+
+        timer.write_tac(0b001);
+
+        assert_eq!(timer.tima, 1);
     }
 }
@@ -1,8 +1,8 @@
 use std::io;
+use std::io::Write;
 
 use crate::{cpu::registers::combine, cpu::Cpu};
 
-pub const DEBUG_SPRITES: bool = true;
 pub const DEBUG_RENDERING: bool = false;
 const PAUSE: bool = false;
 const TEST_ROM: bool = false;
@@ -23,7 +23,10 @@ pub fn handle_debug(opcode: u8, cpu: &mut Cpu) {
         println!("{}", cpu.memory.ppu)
     }
     if DEBUG_MODE {
-        display_opcode(opcode, cpu);
+        println!("{}", format_opcode(opcode, cpu));
+    }
+    if cpu.trace.is_some() {
+        trace_opcode(opcode, cpu);
     }
     if PAUSE {
         let mut buf = String::new();
@@ -73,28 +76,37 @@ fn should_print() -> bool {
         false
     }
 }
-fn display_opcode(opcode: u8, cpu: &mut Cpu) {
+fn trace_opcode(opcode: u8, cpu: &mut Cpu) {
+    let line = format_opcode(opcode, cpu);
+    if let Some(trace) = cpu.trace.as_mut() {
+        if writeln!(trace, "{}", line).is_err() {
+            eprintln!("Error while writing trace, tracing stopped");
+            cpu.trace = None;
+        }
+    }
+}
+
+fn format_opcode(opcode: u8, cpu: &mut Cpu) -> String {
     let mut opcode_display = opcode;
-    if opcode == 0xcb {
+    let mut retval = if opcode == 0xcb {
         opcode_display = cpu.memory.read(cpu.memory.pc);
-        print!(
+        format!(
             "${:<04x}: cb {:02x} |",
             cpu.memory.pc.wrapping_sub(1),
             opcode_display
-        );
+        )
     } else {
-        print!(
+        format!(
             "${:<04x}: {:02x}    |",
             cpu.memory.pc.wrapping_sub(1),
             opcode_display
-        );
-    }
-    print!(" {:20} |", diassemble(opcode, cpu));
-    print!("{}", cpu.reg);
-    print!(" | cycles: {}", cpu.memory.cycle.wrapping_sub(1));
-    print!(" | iflag: {:0>5b}", cpu.memory.interrupt.iflag);
-    //display_stack(cpu);
-    println!();
+        )
+    };
+    retval.push_str(&format!(" {:20} |", diassemble(opcode, cpu)));
+    retval.push_str(&format!("{}", cpu.reg));
+    retval.push_str(&format!(" | cycles: {}", cpu.memory.cycle.wrapping_sub(1)));
+    retval.push_str(&format!(" | iflag: {:0>5b}", cpu.memory.interrupt.iflag));
+    retval
 }
 
 #[allow(dead_code)]
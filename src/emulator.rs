@@ -0,0 +1,472 @@
+use std::io::Write;
+
+use crate::apu::filter::AudioFilter;
+use crate::apu::{Apu, DEFAULT_SAMPLE_RATE};
+use crate::cartridge::header::Header;
+use crate::cartridge::{get_checksum, Cartridge, BOOTROM_SIZE};
+use crate::cpu::Cpu;
+use crate::joypad::{Buttons, DEFAULT_TURBO_RATE};
+use crate::pacing::DOTS_PER_FRAME;
+use crate::ppu::color::{DmgPalettes, GREY_PALETTE};
+use crate::ppu::compat::get_compat_palettes;
+use crate::savestate::{load_state, save_state, StateError};
+
+const CYCLES_PER_FRAME: u128 = DOTS_PER_FRAME as u128 / 4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Model {
+    Dmg,
+    Cgb,
+}
+
+impl Model {
+    pub fn detect(rom: &[u8]) -> Model {
+        match rom.get(0x143) {
+            Some(flag) if flag & 0x80 != 0 => Model::Cgb,
+            _ => Model::Dmg,
+        }
+    }
+}
+
+pub struct EmulatorOptions {
+    pub boot_rom: Option<[u8; BOOTROM_SIZE]>,
+    pub palettes: DmgPalettes,
+    pub model: Option<Model>,
+    pub boot_buttons: Buttons,
+    pub sample_rate: u32,
+    pub audio_filter: Option<AudioFilter>,
+    pub block_opposing_directions: bool,
+    pub turbo_rate: u32,
+}
+
+impl Default for EmulatorOptions {
+    fn default() -> EmulatorOptions {
+        EmulatorOptions {
+            boot_rom: None,
+            palettes: DmgPalettes::uniform(GREY_PALETTE),
+            model: None,
+            boot_buttons: Buttons::default(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            audio_filter: None,
+            block_opposing_directions: false,
+            turbo_rate: DEFAULT_TURBO_RATE,
+        }
+    }
+}
+
+pub struct Emulator {
+    cpu: Cpu,
+    model: Model,
+    boot_rom_checksum: Option<u32>,
+    frame: u64,
+    frame_start: u128,
+}
+
+impl Emulator {
+    pub fn new(rom: &[u8], options: EmulatorOptions) -> Emulator {
+        let cartridge = Cartridge::new(rom, options.boot_rom);
+        let model = options.model.unwrap_or_else(|| Model::detect(rom));
+        let cgb = model == Model::Cgb && cartridge.header().supports_cgb();
+        let compat_palettes = match model {
+            Model::Cgb if !cgb => Some(get_compat_palettes(
+                cartridge.header(),
+                options.boot_buttons,
+            )),
+            _ => None,
+        };
+        let mut cpu = Cpu::with_cartridge(cartridge, cgb);
+        match compat_palettes {
+            Some(palettes) => cpu.memory.ppu.set_palettes(palettes),
+            None => cpu.memory.ppu.set_palettes(options.palettes),
+        }
+        cpu.memory
+            .joypad
+            .set_block_opposing(options.block_opposing_directions);
+        cpu.memory.joypad.set_turbo_rate(options.turbo_rate);
+        cpu.memory.apu.set_sample_rate(options.sample_rate);
+        cpu.memory
+            .apu
+            .set_filter(options.audio_filter.unwrap_or(match model {
+                Model::Dmg => AudioFilter::Dmg,
+                Model::Cgb => AudioFilter::Cgb,
+            }));
+        Emulator {
+            cpu,
+            model,
+            boot_rom_checksum: options.boot_rom.map(|boot_rom| get_checksum(&boot_rom)),
+            frame: 0,
+            frame_start: 0,
+        }
+    }
+
+    pub fn run_frame(&mut self) {
+        while !self.step_instruction() {}
+    }
+
+    pub fn step_instruction(&mut self) -> bool {
+        self.cpu.step();
+        let budget = if self.cpu.memory.double_speed {
+            CYCLES_PER_FRAME * 2
+        } else {
+            CYCLES_PER_FRAME
+        };
+        let lcd_off_frame = self.cpu.memory.cycle - self.frame_start >= budget;
+        if !self.cpu.memory.ppu.frame_drawn && !lcd_off_frame {
+            return false;
+        }
+        self.cpu.memory.ppu.frame_drawn = false;
+        self.cpu.memory.joypad.clock_frame();
+        self.frame += 1;
+        self.frame_start = self.cpu.memory.cycle;
+        true
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn rom_checksum(&self) -> u32 {
+        self.cpu.memory.rom_checksum()
+    }
+
+    pub fn boot_rom_checksum(&self) -> Option<u32> {
+        self.boot_rom_checksum
+    }
+
+    pub fn cycles(&self) -> u128 {
+        self.cpu.memory.cycle * 4
+    }
+
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.cpu.memory.joypad.set_buttons(buttons);
+    }
+
+    pub fn set_turbo(&mut self, turbo_a: bool, turbo_b: bool) {
+        self.cpu.memory.joypad.set_turbo(turbo_a, turbo_b);
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        self.cpu.memory.joypad.pressed()
+    }
+
+    pub fn framebuffer(&self) -> &[u32] {
+        &self.cpu.memory.ppu.video_buffer
+    }
+
+    pub fn frame_hash(&self) -> u64 {
+        self.cpu.memory.ppu.frame_hash()
+    }
+
+    pub fn tiles_buffer(&mut self) -> &[u32] {
+        self.cpu.memory.ppu.get_tiles_memory()
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.cpu.memory.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.cpu.memory.apu
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.cpu.memory.apu.sample_rate()
+    }
+
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.cpu.memory.apu.take_samples()
+    }
+
+    pub fn set_record_channels(&mut self, record_channels: bool) {
+        self.cpu.memory.apu.set_record_channels(record_channels);
+    }
+
+    pub fn channel_samples(&mut self) -> Vec<[f32; 4]> {
+        self.cpu.memory.apu.take_channel_samples()
+    }
+
+    pub fn serial_output(&mut self) -> Vec<u8> {
+        self.cpu.memory.serial.take_output()
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        save_state(&self.cpu, self.frame)
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        self.frame = load_state(&mut self.cpu, data)?;
+        self.frame_start = self.cpu.memory.cycle;
+        Ok(())
+    }
+
+    pub fn save_ram(&self) -> &[u8] {
+        self.cpu.memory.cartridge.ram()
+    }
+
+    pub fn load_save_ram(&mut self, data: &[u8]) {
+        self.cpu.memory.cartridge.load_ram(data);
+    }
+
+    pub fn header(&self) -> &Header {
+        self.cpu.memory.cartridge.header()
+    }
+
+    pub fn set_palettes(&mut self, palettes: DmgPalettes) {
+        self.cpu.memory.ppu.set_palettes(palettes);
+    }
+
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write>>) {
+        self.cpu.trace = trace;
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::color::get_palette;
+
+    fn rom_with_program(program: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+        rom
+    }
+
+    #[test]
+    fn it_should_start_after_boot_rom() {
+        let rom = rom_with_program(&[0x3E, 0x42]);
+        let mut emulator = Emulator::new(&rom, EmulatorOptions::default());
+
+        emulator.step_instruction();
+
+        assert_eq!(emulator.cpu().reg.a, 0x42);
+        assert_eq!(emulator.cpu().memory.pc, 0x102);
+    }
+
+    #[test]
+    fn it_should_run_a_frame() {
+        let rom = rom_with_program(&[0x18, 0xFE]);
+        let mut emulator = Emulator::new(&rom, EmulatorOptions::default());
+
+        emulator.run_frame();
+        emulator.run_frame();
+
+        assert_eq!(emulator.frame(), 2);
+        assert_eq!(emulator.framebuffer().len(), 160 * 144);
+    }
+
+    #[test]
+    fn it_should_generate_audio_samples_each_frame() {
+        let rom = rom_with_program(&[0x18, 0xFE]);
+        let options = EmulatorOptions {
+            sample_rate: 32_768,
+            ..EmulatorOptions::default()
+        };
+        let mut emulator = Emulator::new(&rom, options);
+
+        emulator.run_frame();
+        emulator.audio_samples();
+        emulator.run_frame();
+        let samples = emulator.audio_samples();
+
+        assert!((2 * 548..=2 * 549).contains(&samples.len()));
+        assert!(emulator.audio_samples().is_empty());
+    }
+
+    #[test]
+    fn it_should_capture_serial_output() {
+        let rom = rom_with_program(&[0x3E, b'O', 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE]);
+        let mut emulator = Emulator::new(&rom, EmulatorOptions::default());
+
+        emulator.run_frame();
+
+        assert_eq!(emulator.serial_output(), b"O");
+        assert!(emulator.serial_output().is_empty());
+    }
+
+    #[test]
+    fn it_should_hash_the_framebuffer() {
+        let rom = rom_with_program(&[0x18, 0xFE]);
+        let mut first = Emulator::new(&rom, EmulatorOptions::default());
+        let mut second = Emulator::new(&rom, EmulatorOptions::default());
+
+        first.run_frame();
+        second.run_frame();
+        let hash = first.frame_hash();
+        first.cpu_mut().memory.ppu.shade_buffer[0] ^= 1;
+
+        assert_eq!(hash, second.frame_hash());
+        assert_ne!(hash, first.frame_hash());
+    }
+
+    #[test]
+    fn it_should_not_hash_the_host_palette() {
+        let rom = rom_with_program(&[0x18, 0xFE]);
+        let mut grey = Emulator::new(&rom, EmulatorOptions::default());
+        let mut green = Emulator::new(
+            &rom,
+            EmulatorOptions {
+                palettes: DmgPalettes::uniform(get_palette("green").unwrap()),
+                ..EmulatorOptions::default()
+            },
+        );
+
+        grey.run_frame();
+        green.run_frame();
+
+        assert_ne!(grey.framebuffer(), green.framebuffer());
+        assert_eq!(grey.frame_hash(), green.frame_hash());
+    }
+
+    fn cgb_rom_with_program(program: &[u8]) -> Vec<u8> {
+        let mut rom = rom_with_program(program);
+        rom[0x143] = 0x80;
+        rom
+    }
+
+    #[test]
+    fn it_should_select_model_from_header() {
+        let dmg = Emulator::new(&rom_with_program(&[]), EmulatorOptions::default());
+        let cgb = Emulator::new(&cgb_rom_with_program(&[]), EmulatorOptions::default());
+
+        assert_eq!(dmg.model(), Model::Dmg);
+        assert_eq!(cgb.model(), Model::Cgb);
+        assert_eq!(cgb.cpu().reg.a, 0x11);
+    }
+
+    #[test]
+    fn it_should_colorize_dmg_game_on_cgb() {
+        let rom = rom_with_program(&[0x3E, 0x55, 0xE0, 0x47, 0x18, 0xFE]);
+        let options = EmulatorOptions {
+            model: Some(Model::Cgb),
+            ..EmulatorOptions::default()
+        };
+        let mut emulator = Emulator::new(&rom, options);
+
+        emulator.run_frame();
+        emulator.run_frame();
+
+        assert!(!emulator.cpu().memory.is_cgb());
+        assert_eq!(emulator.framebuffer()[0], 0x7BFF31);
+    }
+
+    #[test]
+    fn it_should_switch_wram_banks() {
+        let mut emulator = Emulator::new(&cgb_rom_with_program(&[]), EmulatorOptions::default());
+        let memory = &mut emulator.cpu_mut().memory;
+
+        memory.write(0xFF70, 2);
+        memory.write(0xD000, 0x11);
+        memory.write(0xFF70, 3);
+        memory.write(0xD000, 0x22);
+        memory.write(0xFF70, 2);
+
+        assert_eq!(memory.read(0xD000), 0x11);
+        assert_eq!(memory.read(0xFF70), 0xFA);
+    }
+
+    #[test]
+    fn it_should_switch_to_double_speed() {
+        let rom = cgb_rom_with_program(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x18, 0xFE]);
+        let mut emulator = Emulator::new(&rom, EmulatorOptions::default());
+
+        for _ in 0..4 {
+            emulator.step_instruction();
+        }
+
+        assert!(emulator.cpu().memory.double_speed);
+        assert_eq!(emulator.cpu_mut().memory.read(0xFF4D), 0xFE);
+    }
+
+    #[test]
+    fn it_should_copy_general_purpose_hdma() {
+        let mut emulator = Emulator::new(&cgb_rom_with_program(&[]), EmulatorOptions::default());
+        let memory = &mut emulator.cpu_mut().memory;
+        for offset in 0..0x20 {
+            memory.write(0xC000 + offset, offset as u8);
+        }
+
+        memory.write(0xFF51, 0xC0);
+        memory.write(0xFF52, 0x00);
+        memory.write(0xFF53, 0x01);
+        memory.write(0xFF54, 0x00);
+        memory.write(0xFF55, 0x01);
+        memory.write(0xFF40, 0x00);
+
+        assert_eq!(memory.read(0x811F), 0x1F);
+        assert_eq!(memory.read(0xFF55), 0xFF);
+    }
+
+    #[test]
+    fn it_should_round_trip_save_state() {
+        let rom = rom_with_program(&[0x3E, 0x42, 0x18, 0xFE]);
+        let mut emulator = Emulator::new(&rom, EmulatorOptions::default());
+        let state = emulator.save_state();
+
+        emulator.step_instruction();
+        emulator.load_state(&state).unwrap();
+
+        assert_eq!(emulator.cpu().reg.a, 0x01);
+        assert_eq!(emulator.cpu().memory.pc, 0x100);
+    }
+
+    #[test]
+    fn it_should_restore_the_frame_counter() {
+        let rom = rom_with_program(&[0x18, 0xFE]);
+        let mut emulator = Emulator::new(&rom, EmulatorOptions::default());
+        emulator.run_frame();
+        let state = emulator.save_state();
+
+        emulator.run_frame();
+        emulator.load_state(&state).unwrap();
+
+        assert_eq!(emulator.frame(), 1);
+    }
+
+    #[test]
+    fn it_should_keep_running_game_after_failed_load() {
+        let rom = cgb_rom_with_program(&[0x3E, 0x42, 0x18, 0xFE]);
+        let mut emulator = Emulator::new(&rom, EmulatorOptions::default());
+        let mut dmg = Emulator::new(
+            &rom,
+            EmulatorOptions {
+                model: Some(Model::Dmg),
+                ..EmulatorOptions::default()
+            },
+        );
+        dmg.run_frame();
+        emulator.run_frame();
+        let before = emulator.save_state();
+
+        let truncated = &before[..before.len() - 100];
+        assert!(emulator.load_state(truncated).is_err());
+        assert_eq!(emulator.save_state(), before);
+        assert!(emulator.load_state(&dmg.save_state()).is_err());
+        assert_eq!(emulator.save_state(), before);
+    }
+
+    #[test]
+    fn it_should_expose_save_ram() {
+        let mut rom = rom_with_program(&[]);
+        rom[0x147] = 0x09;
+        rom[0x149] = 0x02;
+        let mut emulator = Emulator::new(&rom, EmulatorOptions::default());
+
+        emulator.load_save_ram(&[1, 2, 3]);
+        emulator.cpu_mut().memory.write(0xA003, 4);
+
+        assert_eq!(emulator.save_ram().len(), 0x2000);
+        assert_eq!(&emulator.save_ram()[..4], &[1, 2, 3, 4]);
+    }
+}
@@ -1,41 +1,440 @@
 use crate::{
-    debug_tools::DEBUG_SPRITES,
+    cli::Options,
+    config::{find_palette, Config},
+    gamepad::{GamepadSource, Gamepads, NoGamepad},
+    keyboard::{read_buttons, Action, KeyBindings},
+    recorder::{record_audio, toggle_recording, Recorder},
+    sound_debug::{handle_channel_keys, AudioWindow},
+    speed::SpeedControl,
+};
+use game_boy::{
+    apu::DEFAULT_SAMPLE_RATE,
+    audio::{AudioOutput, AudioSink, NullSink},
+    cartridge::{load_bootrom, load_rom, DEFAULT_BOOTROM},
+    emulator::{Emulator, EmulatorOptions, Model},
+    input_script::InputScript,
+    joypad::Buttons,
+    movie::{Movie, EMULATOR_VERSION},
+    pacing::{FramePacer, SyncMode},
+    png::encode_png,
+    ppu::color::DmgPalettes,
     ppu::config::{DEBUG_HEIGHT, DEBUG_WIDTH, HEIGHT, WIDTH},
+    rewind::{Rewind, REWIND_CAPACITY, REWIND_INTERVAL},
+    savestate::{read_slot, write_slot, StateError},
+};
+use minifb::Window;
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
 };
-use minifb::{Key, Window};
-use std::time::Instant;
 
-use crate::cpu::Cpu;
-pub fn run_gameboy(window: &mut Window, debug_window: &mut Option<Window>) {
-    let mut cpu = Cpu::new();
-    loop {
-        let start = Instant::now();
-        cpu.step();
-        if cpu.memory.ppu.frame_drawn {
-            cpu.memory.ppu.frame_drawn = false;
-            cpu.memory.joypad.update(window);
-            render(&mut cpu, window, debug_window);
-            while start.elapsed().as_millis() < 1 {}
+const STATE_SLOTS: u8 = 4;
+
+pub fn build_emulator(options: &Options, config: &Config) -> Result<Emulator, String> {
+    let rom = load_rom(&options.rom)?;
+    let palettes = config.palettes();
+    let palette = find_palette(&palettes, &options.palette)?;
+    let model = options.model.unwrap_or_else(|| Model::detect(&rom));
+    let boot_rom = match &options.boot_rom {
+        Some(_) if model == Model::Cgb => {
+            return Err(String::from("--boot-rom only supports the dmg model"))
+        }
+        Some(filename) => Some(load_bootrom(filename)?),
+        None if model == Model::Dmg && Path::new(DEFAULT_BOOTROM).exists() => {
+            Some(load_bootrom(DEFAULT_BOOTROM)?)
+        }
+        None => None,
+    };
+    let mut emulator = Emulator::new(
+        &rom,
+        EmulatorOptions {
+            boot_rom,
+            palettes: palettes[palette].1,
+            model: Some(model),
+            boot_buttons: options.boot_buttons,
+            audio_filter: options.audio_filter,
+            block_opposing_directions: options.block_opposing,
+            turbo_rate: options.turbo_rate,
+            ..EmulatorOptions::default()
+        },
+    );
+    if let Some(filename) = &options.trace {
+        let file = File::create(filename)
+            .map_err(|err| format!("cannot create trace file {}: {}", filename, err))?;
+        emulator.set_trace(Some(Box::new(BufWriter::new(file))));
+    }
+    Ok(emulator)
+}
+
+pub fn run_headless(
+    mut emulator: Emulator,
+    options: &Options,
+    movie: Option<Movie>,
+) -> Result<(), String> {
+    let script = match &options.input {
+        Some(filename) => Some(load_script(filename)?),
+        None => None,
+    };
+    let mut recorder = match &options.wav {
+        Some(filename) => Some(Recorder::start(
+            filename,
+            emulator.sample_rate(),
+            options.wav_channels,
+        )?),
+        None => None,
+    };
+    emulator.set_record_channels(options.wav_channels);
+    let mut recording = options.record_movie.as_ref().map(|_| Movie::new(&emulator));
+    let mut stdout = io::stdout();
+    while !is_headless_done(&emulator, options, movie.as_ref()) {
+        let buttons = match (&movie, &script) {
+            (Some(movie), _) => movie.buttons_at(emulator.frame()),
+            (None, Some(script)) => script.buttons_at(emulator.frame()),
+            (None, None) => Buttons::default(),
+        };
+        emulator.set_buttons(buttons);
+        if let Some(recording) = &mut recording {
+            recording.record(emulator.frame(), emulator.buttons());
         }
-        handle_exit(window);
+        while !emulator.step_instruction() && !is_headless_done(&emulator, options, movie.as_ref())
+        {
+        }
+        stdout
+            .write_all(&emulator.serial_output())
+            .map_err(|err| format!("cannot write serial output: {}", err))?;
+        let samples = emulator.audio_samples();
+        if let Some(recorder) = &mut recorder {
+            recorder.record(&samples, &emulator.channel_samples())?;
+        }
+    }
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+    stdout
+        .flush()
+        .map_err(|err| format!("cannot write serial output: {}", err))?;
+    if let Some(filename) = &options.png {
+        fs::write(filename, encode_png(emulator.framebuffer(), WIDTH, HEIGHT))
+            .map_err(|err| format!("cannot write {}: {}", filename, err))?;
+    }
+    eprintln!("Frame hash: {:016x}", emulator.frame_hash());
+    if let (Some(filename), Some(mut recording)) = (&options.record_movie, recording) {
+        recording.finish(&emulator);
+        write_movie(filename, &recording)?;
     }
+    if let (true, Some(movie)) = (options.verify_movie, &movie) {
+        movie
+            .verify(&emulator)
+            .map_err(|err| format!("the movie desynced, {}", err))?;
+        eprintln!("Movie verified");
+    }
+    Ok(())
+}
+
+fn is_headless_done(emulator: &Emulator, options: &Options, movie: Option<&Movie>) -> bool {
+    options
+        .frames
+        .is_some_and(|limit| emulator.frame() >= limit)
+        || options
+            .cycles
+            .is_some_and(|limit| emulator.cycles() >= limit as u128)
+        || movie.is_some_and(|movie| movie.is_finished(emulator.frame()))
 }
 
-fn render(cpu: &mut Cpu, window: &mut Window, debug_window: &mut Option<Window>) {
-    let video = cpu.memory.ppu.get_video_buffer();
-    window.update_with_buffer(video, WIDTH, HEIGHT).unwrap();
-    if !DEBUG_SPRITES {
-        return;
+pub fn load_movie(options: &Options, emulator: &Emulator) -> Result<Option<Movie>, String> {
+    let Some(filename) = &options.movie else {
+        return Ok(None);
+    };
+    let text = fs::read_to_string(filename)
+        .map_err(|err| format!("cannot read movie {}: {}", filename, err))?;
+    let movie = Movie::parse(&text).map_err(|err| format!("{}: {}", filename, err))?;
+    movie
+        .check(emulator)
+        .map_err(|err| format!("{}: {}", filename, err))?;
+    if movie.emulator_version != EMULATOR_VERSION {
+        eprintln!(
+            "Warning: {} was recorded with version {} of the emulator",
+            filename, movie.emulator_version
+        );
     }
+    Ok(Some(movie))
+}
+
+fn write_movie(filename: &str, movie: &Movie) -> Result<(), String> {
+    fs::write(filename, movie.to_text())
+        .map_err(|err| format!("cannot write movie {}: {}", filename, err))?;
+    println!("Movie of {} frames saved in {}", movie.frames, filename);
+    Ok(())
+}
+
+fn load_script(filename: &str) -> Result<InputScript, String> {
+    let script = fs::read_to_string(filename)
+        .map_err(|err| format!("cannot read input script {}: {}", filename, err))?;
+    InputScript::parse(&script).map_err(|err| format!("{}: {}", filename, err))
+}
+
+pub fn run_gameboy(
+    mut emulator: Emulator,
+    options: &Options,
+    config: &Config,
+    window: &mut Window,
+    debug_window: &mut Option<Window>,
+    audio_window: &mut Option<AudioWindow>,
+    mut movie: Option<Movie>,
+) {
+    let rom_filename = &options.rom;
+    let keys = &config.keys;
+    let palettes = config.palettes();
+    let mut palette = find_palette(&palettes, &options.palette).unwrap_or(0);
+    let mut rewind = Rewind::new(REWIND_INTERVAL, REWIND_CAPACITY);
+    let mut pacer = FramePacer::new();
+    let mut speed = SpeedControl::new(options.fast_forward);
+    let (mut audio, synced) = open_synced_audio(options.sync);
+    let mut gamepad_source = open_gamepads();
+    let mut gamepads = Gamepads::new(config.gamepad.clone());
+    let mut recorder = None;
+    if options.wav.is_some() {
+        toggle_recording(emulator.sample_rate(), options, &mut recorder);
+    }
+    emulator.set_record_channels(options.wav_channels || audio_window.is_some());
+    let mut recording = options.record_movie.as_ref().map(|_| Movie::new(&emulator));
+    if let Some(movie) = &movie {
+        emulator.set_buttons(movie.buttons_at(0));
+    }
+    loop {
+        gamepads.update(gamepad_source.as_mut());
+        speed.handle_keys(window, keys);
+        pacer.set_speed(speed.speed());
+        let run_frame = speed.should_run_frame();
+        if run_frame {
+            // Going back in time would desync the movie.
+            let can_rewind = movie.is_none() && recording.is_none();
+            if !(can_rewind && handle_rewind(&mut emulator, window, keys, &mut rewind)) {
+                emulator.run_frame();
+                if can_rewind {
+                    rewind.record(&emulator);
+                }
+                match &movie {
+                    Some(movie) => emulator.set_buttons(movie.buttons_at(emulator.frame())),
+                    None => {
+                        emulator.set_buttons(read_buttons(window, keys) | gamepads.buttons());
+                        emulator.set_turbo(
+                            keys.is_down(window, Action::TurboA)
+                                || gamepads.is_down(Action::TurboA),
+                            keys.is_down(window, Action::TurboB)
+                                || gamepads.is_down(Action::TurboB),
+                        );
+                    }
+                }
+                // The turbo presses are recorded as plain presses.
+                if let Some(recording) = &mut recording {
+                    recording.record(emulator.frame(), emulator.buttons());
+                }
+                if movie
+                    .as_ref()
+                    .is_some_and(|movie| movie.is_finished(emulator.frame()))
+                {
+                    println!("Movie finished at frame {}", emulator.frame());
+                    movie = None;
+                }
+            }
+            let samples = emulator.audio_samples();
+            let channel_samples = emulator.channel_samples();
+            audio.push(&samples, emulator.sample_rate());
+            record_audio(&samples, &channel_samples, &mut recorder);
+            if let Some(audio_window) = audio_window {
+                audio_window.push(&channel_samples);
+            }
+        }
+        if !speed.is_fast_forward() || pacer.should_present() {
+            render(&mut emulator, window, debug_window, audio_window);
+            let can_load = movie.is_none() && recording.is_none();
+            handle_save_states(&mut emulator, window, keys, rom_filename, can_load);
+            handle_palette(&mut emulator, window, keys, &palettes, &mut palette);
+            handle_channel_keys(window, keys, emulator.apu_mut());
+            if keys.is_pressed(window, Action::Record) {
+                toggle_recording(emulator.sample_rate(), options, &mut recorder);
+            }
+            if keys.is_pressed(window, Action::Screenshot) {
+                save_screenshot(&emulator, rom_filename);
+            }
+        }
+        // The sound only sets the pace of frames played at normal speed.
+        let measured = if synced && run_frame && speed.speed() == Some(1.0) {
+            pacer.wait_for_audio(audio.sink())
+        } else {
+            pacer.wait()
+        };
+        if measured {
+            update_title(window, &pacer, &speed);
+        }
+        if options
+            .frames
+            .is_some_and(|limit| emulator.frame() >= limit)
+            || handle_exit(window, keys)
+        {
+            if recorder.is_some() {
+                toggle_recording(emulator.sample_rate(), options, &mut recorder);
+            }
+            if let (Some(filename), Some(mut recording)) = (&options.record_movie, recording) {
+                recording.finish(&emulator);
+                if let Err(err) = write_movie(filename, &recording) {
+                    eprintln!("Error: {}", err);
+                }
+            }
+            return;
+        }
+    }
+}
+
+fn render(
+    emulator: &mut Emulator,
+    window: &mut Window,
+    debug_window: &mut Option<Window>,
+    audio_window: &mut Option<AudioWindow>,
+) {
+    window
+        .update_with_buffer(emulator.framebuffer(), WIDTH, HEIGHT)
+        .unwrap();
     if let Some(ref mut w) = debug_window {
-        let mem = cpu.memory.ppu.get_tiles_memory();
-        w.update_with_buffer(mem, DEBUG_WIDTH, DEBUG_HEIGHT)
+        w.update_with_buffer(emulator.tiles_buffer(), DEBUG_WIDTH, DEBUG_HEIGHT)
             .unwrap();
     }
+    if let Some(audio_window) = audio_window {
+        audio_window.render(emulator.apu());
+    }
 }
 
-fn handle_exit(window: &mut Window) {
-    if window.is_key_down(Key::Escape) || !window.is_open() {
-        std::process::exit(0);
+fn update_title(window: &mut Window, pacer: &FramePacer, speed: &SpeedControl) {
+    if speed.is_paused() {
+        window.set_title("Gameboy - paused");
+    } else {
+        window.set_title(&format!(
+            "Gameboy - {:.1} fps ({:.0}%)",
+            pacer.fps(),
+            pacer.speed()
+        ));
     }
 }
+
+// The restored state holds its frame, it is shown in place of running one.
+fn handle_rewind(
+    emulator: &mut Emulator,
+    window: &Window,
+    keys: &KeyBindings,
+    rewind: &mut Rewind,
+) -> bool {
+    if !keys.is_down(window, Action::Rewind) {
+        return false;
+    }
+    rewind.step_back(emulator);
+    true
+}
+
+fn handle_save_states(
+    emulator: &mut Emulator,
+    window: &Window,
+    keys: &KeyBindings,
+    rom_filename: &str,
+    can_load: bool,
+) {
+    for slot in 1..=STATE_SLOTS {
+        if keys.is_pressed(window, Action::Save(slot)) {
+            match write_slot(rom_filename, slot, &emulator.save_state()) {
+                Ok(()) => println!("State saved in slot {}", slot),
+                Err(err) => eprintln!("Error while saving slot {}: {}", slot, err),
+            }
+        }
+        if keys.is_pressed(window, Action::Load(slot)) && !can_load {
+            eprintln!("States cannot be loaded while a movie is recorded or played");
+        } else if keys.is_pressed(window, Action::Load(slot)) {
+            match load_slot(emulator, rom_filename, slot) {
+                Ok(()) => println!("State loaded from slot {}", slot),
+                Err(err) => eprintln!("Error while loading slot {}: {}", slot, err),
+            }
+        }
+    }
+}
+
+fn save_screenshot(emulator: &Emulator, rom_filename: &str) {
+    let filename = (1..)
+        .map(|index| format!("{}.{}.png", rom_filename, index))
+        .find(|filename| !Path::new(filename).exists())
+        .unwrap();
+    match fs::write(&filename, encode_png(emulator.framebuffer(), WIDTH, HEIGHT)) {
+        Ok(()) => println!("Screenshot saved in {}", filename),
+        Err(err) => eprintln!("Error while saving {}: {}", filename, err),
+    }
+}
+
+fn handle_palette(
+    emulator: &mut Emulator,
+    window: &Window,
+    keys: &KeyBindings,
+    palettes: &[(String, DmgPalettes)],
+    palette: &mut usize,
+) {
+    if keys.is_pressed(window, Action::Palette) {
+        *palette = (*palette + 1) % palettes.len();
+        let (name, colors) = &palettes[*palette];
+        emulator.set_palettes(*colors);
+        println!("Palette: {}", name);
+    }
+}
+
+// Syncing to the sound needs a device that plays the samples.
+pub fn open_synced_audio(sync: SyncMode) -> (AudioOutput<Box<dyn AudioSink>>, bool) {
+    let device = open_audio_device();
+    if sync == SyncMode::Audio && device.is_none() {
+        eprintln!("Warning: no sound device to sync to, frames follow the wall clock");
+    }
+    let synced = sync == SyncMode::Audio && device.is_some();
+    let sink = device.unwrap_or_else(|| Box::new(get_silent_sink()));
+    (AudioOutput::new(sink), synced)
+}
+
+#[cfg(feature = "audio")]
+fn open_audio_device() -> Option<Box<dyn AudioSink>> {
+    match crate::audio_device::DeviceSink::open() {
+        Ok(sink) => Some(Box::new(sink)),
+        Err(err) => {
+            eprintln!("Warning: sound disabled, {}", err);
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+fn open_audio_device() -> Option<Box<dyn AudioSink>> {
+    None
+}
+
+#[cfg(feature = "gamepad")]
+pub fn open_gamepads() -> Box<dyn GamepadSource> {
+    match crate::gamepad_device::GilrsSource::open() {
+        Ok(source) => Box::new(source),
+        Err(err) => {
+            eprintln!("Warning: gamepads disabled, {}", err);
+            Box::new(NoGamepad)
+        }
+    }
+}
+
+#[cfg(not(feature = "gamepad"))]
+pub fn open_gamepads() -> Box<dyn GamepadSource> {
+    Box::new(NoGamepad)
+}
+
+fn get_silent_sink() -> NullSink {
+    NullSink::new(DEFAULT_SAMPLE_RATE, DEFAULT_SAMPLE_RATE as usize / 10)
+}
+
+fn load_slot(emulator: &mut Emulator, rom_filename: &str, slot: u8) -> Result<(), StateError> {
+    let data = read_slot(rom_filename, slot)?;
+    emulator.load_state(&data)
+}
+
+pub fn handle_exit(window: &mut Window, keys: &KeyBindings) -> bool {
+    keys.is_down(window, Action::Quit) || !window.is_open()
+}
@@ -0,0 +1,338 @@
+use crate::keyboard::{parse_action, Action};
+use game_boy::joypad::Buttons;
+
+// Past this deflection the left stick presses a direction.
+const STICK_THRESHOLD: f32 = 0.5;
+
+// Buttons named by their position, South being A on an Xbox pad and B on a
+// Nintendo one.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GamepadButton {
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    South,
+    East,
+    North,
+    West,
+    Start,
+    Select,
+    LeftTrigger,
+    RightTrigger,
+}
+
+const BUTTONS: [(&str, GamepadButton); 12] = [
+    ("dpad_up", GamepadButton::DPadUp),
+    ("dpad_down", GamepadButton::DPadDown),
+    ("dpad_left", GamepadButton::DPadLeft),
+    ("dpad_right", GamepadButton::DPadRight),
+    ("south", GamepadButton::South),
+    ("east", GamepadButton::East),
+    ("north", GamepadButton::North),
+    ("west", GamepadButton::West),
+    ("start", GamepadButton::Start),
+    ("select", GamepadButton::Select),
+    ("left_trigger", GamepadButton::LeftTrigger),
+    ("right_trigger", GamepadButton::RightTrigger),
+];
+
+// The joypad actions with their default buttons, the other actions stay on
+// the keyboard.
+const ACTIONS: [(Action, &[GamepadButton]); 10] = [
+    (Action::Right, &[GamepadButton::DPadRight]),
+    (Action::Left, &[GamepadButton::DPadLeft]),
+    (Action::Up, &[GamepadButton::DPadUp]),
+    (Action::Down, &[GamepadButton::DPadDown]),
+    (Action::A, &[GamepadButton::East]),
+    (Action::B, &[GamepadButton::South]),
+    (Action::Select, &[GamepadButton::Select]),
+    (Action::Start, &[GamepadButton::Start]),
+    (Action::TurboA, &[GamepadButton::North]),
+    (Action::TurboB, &[GamepadButton::West]),
+];
+
+// The stick reads from -1 to 1, up and right being positive.
+#[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+}
+
+// Only the device source builds events outside of tests, it needs the gamepad
+// feature.
+#[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+#[derive(Clone, Debug, PartialEq)]
+pub enum GamepadEvent {
+    Connected(usize, String),
+    Disconnected(usize),
+    Pressed(usize, GamepadButton),
+    Released(usize, GamepadButton),
+    Axis(usize, GamepadAxis, f32),
+}
+
+// Where the events come from, the host input library or a test.
+pub trait GamepadSource {
+    fn poll(&mut self) -> Vec<GamepadEvent>;
+}
+
+// Used when the emulator is built without gamepad support or none can be read.
+pub struct NoGamepad;
+
+impl GamepadSource for NoGamepad {
+    fn poll(&mut self) -> Vec<GamepadEvent> {
+        vec![]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GamepadBindings {
+    bindings: Vec<(Action, Vec<GamepadButton>)>,
+}
+
+impl Default for GamepadBindings {
+    fn default() -> GamepadBindings {
+        GamepadBindings {
+            bindings: ACTIONS
+                .iter()
+                .map(|(action, buttons)| (*action, buttons.to_vec()))
+                .collect(),
+        }
+    }
+}
+
+impl GamepadBindings {
+    // Replaces the buttons of a joypad action, given as names separated by
+    // spaces or commas.
+    pub fn bind(&mut self, action: &str, buttons: &str) -> Result<(), String> {
+        let action = parse_action(action)?;
+        let buttons = buttons
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|name| !name.is_empty())
+            .map(parse_button)
+            .collect::<Result<Vec<GamepadButton>, String>>()?;
+        match self.bindings.iter_mut().find(|(bound, _)| *bound == action) {
+            Some((_, bound_buttons)) => *bound_buttons = buttons,
+            None => return Err(String::from("only joypad actions go on a gamepad")),
+        }
+        Ok(())
+    }
+
+    pub fn buttons(&self, action: Action) -> &[GamepadButton] {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == action)
+            .map_or(&[], |(_, buttons)| buttons)
+    }
+}
+
+fn parse_button(name: &str) -> Result<GamepadButton, String> {
+    let name = name.to_lowercase();
+    BUTTONS
+        .iter()
+        .find(|(button, _)| *button == name)
+        .map(|(_, button)| *button)
+        .ok_or_else(|| format!("unknown gamepad button {}", name))
+}
+
+struct Gamepad {
+    id: usize,
+    name: String,
+    pressed: Vec<GamepadButton>,
+    stick: (f32, f32),
+}
+
+impl Gamepad {
+    fn is_down(&self, action: Action, bindings: &GamepadBindings) -> bool {
+        let (x, y) = self.stick;
+        let stick = match action {
+            Action::Right => x > STICK_THRESHOLD,
+            Action::Left => x < -STICK_THRESHOLD,
+            Action::Up => y > STICK_THRESHOLD,
+            Action::Down => y < -STICK_THRESHOLD,
+            _ => false,
+        };
+        stick
+            || bindings
+                .buttons(action)
+                .iter()
+                .any(|button| self.pressed.contains(button))
+    }
+}
+
+// The plugged gamepads, any of them can play and they add to the keyboard.
+pub struct Gamepads {
+    gamepads: Vec<Gamepad>,
+    bindings: GamepadBindings,
+}
+
+impl Gamepads {
+    pub fn new(bindings: GamepadBindings) -> Gamepads {
+        Gamepads {
+            gamepads: vec![],
+            bindings,
+        }
+    }
+
+    pub fn update(&mut self, source: &mut dyn GamepadSource) {
+        for event in source.poll() {
+            self.handle_event(event);
+        }
+    }
+
+    fn handle_event(&mut self, event: GamepadEvent) {
+        match event {
+            GamepadEvent::Connected(id, name) => {
+                if self.gamepads.iter().all(|gamepad| gamepad.id != id) {
+                    println!("Gamepad connected: {}", name);
+                    self.gamepads.push(Gamepad {
+                        id,
+                        name,
+                        pressed: vec![],
+                        stick: (0.0, 0.0),
+                    });
+                }
+            }
+            GamepadEvent::Disconnected(id) => {
+                if let Some(index) = self.gamepads.iter().position(|gamepad| gamepad.id == id) {
+                    println!("Gamepad disconnected: {}", self.gamepads[index].name);
+                    self.gamepads.remove(index);
+                }
+            }
+            GamepadEvent::Pressed(id, button) => {
+                if let Some(gamepad) = self.get_gamepad(id) {
+                    if !gamepad.pressed.contains(&button) {
+                        gamepad.pressed.push(button);
+                    }
+                }
+            }
+            GamepadEvent::Released(id, button) => {
+                if let Some(gamepad) = self.get_gamepad(id) {
+                    gamepad.pressed.retain(|pressed| *pressed != button);
+                }
+            }
+            GamepadEvent::Axis(id, axis, value) => {
+                if let Some(gamepad) = self.get_gamepad(id) {
+                    match axis {
+                        GamepadAxis::LeftStickX => gamepad.stick.0 = value,
+                        GamepadAxis::LeftStickY => gamepad.stick.1 = value,
+                    }
+                }
+            }
+        }
+    }
+
+    fn get_gamepad(&mut self, id: usize) -> Option<&mut Gamepad> {
+        self.gamepads.iter_mut().find(|gamepad| gamepad.id == id)
+    }
+
+    pub fn is_down(&self, action: Action) -> bool {
+        self.gamepads
+            .iter()
+            .any(|gamepad| gamepad.is_down(action, &self.bindings))
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        Buttons {
+            right: self.is_down(Action::Right),
+            left: self.is_down(Action::Left),
+            up: self.is_down(Action::Up),
+            down: self.is_down(Action::Down),
+            a: self.is_down(Action::A),
+            b: self.is_down(Action::B),
+            select: self.is_down(Action::Select),
+            start: self.is_down(Action::Start),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockSource {
+        events: Vec<GamepadEvent>,
+    }
+
+    impl GamepadSource for MockSource {
+        fn poll(&mut self) -> Vec<GamepadEvent> {
+            std::mem::take(&mut self.events)
+        }
+    }
+
+    fn send(gamepads: &mut Gamepads, events: Vec<GamepadEvent>) {
+        gamepads.update(&mut MockSource { events });
+    }
+
+    #[test]
+    fn it_should_map_buttons_and_stick() {
+        let mut gamepads = Gamepads::new(GamepadBindings::default());
+
+        send(
+            &mut gamepads,
+            vec![
+                GamepadEvent::Connected(0, String::from("pad")),
+                GamepadEvent::Pressed(0, GamepadButton::East),
+                GamepadEvent::Pressed(0, GamepadButton::Start),
+                GamepadEvent::Axis(0, GamepadAxis::LeftStickY, -0.8),
+                GamepadEvent::Axis(0, GamepadAxis::LeftStickX, 0.3),
+            ],
+        );
+
+        let buttons = gamepads.buttons();
+        assert!(buttons.a && buttons.start && buttons.down);
+        assert!(!buttons.b && !buttons.right && !buttons.up);
+
+        send(
+            &mut gamepads,
+            vec![GamepadEvent::Released(0, GamepadButton::East)],
+        );
+
+        assert!(!gamepads.buttons().a);
+    }
+
+    #[test]
+    fn it_should_handle_hotplug() {
+        let mut gamepads = Gamepads::new(GamepadBindings::default());
+
+        send(
+            &mut gamepads,
+            vec![
+                GamepadEvent::Pressed(3, GamepadButton::South),
+                GamepadEvent::Connected(0, String::from("first")),
+                GamepadEvent::Connected(1, String::from("second")),
+                GamepadEvent::Connected(1, String::from("second")),
+                GamepadEvent::Pressed(1, GamepadButton::North),
+            ],
+        );
+
+        assert!(!gamepads.buttons().b);
+        assert!(gamepads.is_down(Action::TurboA));
+
+        send(
+            &mut gamepads,
+            vec![
+                GamepadEvent::Disconnected(1),
+                GamepadEvent::Pressed(0, GamepadButton::South),
+            ],
+        );
+
+        assert!(!gamepads.is_down(Action::TurboA));
+        assert!(gamepads.buttons().b);
+    }
+
+    #[test]
+    fn it_should_bind_joypad_actions_only() {
+        let mut bindings = GamepadBindings::default();
+
+        bindings.bind("a", "South, right_trigger").unwrap();
+
+        assert_eq!(
+            bindings.buttons(Action::A),
+            [GamepadButton::South, GamepadButton::RightTrigger]
+        );
+        assert!(bindings.bind("pause", "start").is_err());
+        assert!(bindings.bind("a", "triangle").is_err());
+    }
+}
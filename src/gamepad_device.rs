@@ -0,0 +1,81 @@
+use crate::gamepad::{GamepadAxis, GamepadButton, GamepadEvent, GamepadSource};
+use gilrs::{Axis, Button, EventType, Gilrs};
+
+pub struct GilrsSource {
+    gilrs: Gilrs,
+    started: bool,
+}
+
+impl GilrsSource {
+    pub fn open() -> Result<GilrsSource, String> {
+        let gilrs = Gilrs::new().map_err(|err| err.to_string())?;
+        Ok(GilrsSource {
+            gilrs,
+            started: false,
+        })
+    }
+}
+
+impl GamepadSource for GilrsSource {
+    fn poll(&mut self) -> Vec<GamepadEvent> {
+        let mut events = vec![];
+        // The gamepads plugged before the start may not send a connected event.
+        if !self.started {
+            self.started = true;
+            for (id, gamepad) in self.gilrs.gamepads() {
+                events.push(GamepadEvent::Connected(
+                    id.into(),
+                    gamepad.name().to_string(),
+                ));
+            }
+        }
+        while let Some(event) = self.gilrs.next_event() {
+            let id = event.id.into();
+            let event = match event.event {
+                EventType::Connected => Some(GamepadEvent::Connected(
+                    id,
+                    self.gilrs.gamepad(event.id).name().to_string(),
+                )),
+                EventType::Disconnected => Some(GamepadEvent::Disconnected(id)),
+                EventType::ButtonPressed(button, _) => {
+                    get_button(button).map(|button| GamepadEvent::Pressed(id, button))
+                }
+                EventType::ButtonReleased(button, _) => {
+                    get_button(button).map(|button| GamepadEvent::Released(id, button))
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    get_axis(axis).map(|axis| GamepadEvent::Axis(id, axis, value))
+                }
+                _ => None,
+            };
+            events.extend(event);
+        }
+        events
+    }
+}
+
+fn get_button(button: Button) -> Option<GamepadButton> {
+    match button {
+        Button::DPadUp => Some(GamepadButton::DPadUp),
+        Button::DPadDown => Some(GamepadButton::DPadDown),
+        Button::DPadLeft => Some(GamepadButton::DPadLeft),
+        Button::DPadRight => Some(GamepadButton::DPadRight),
+        Button::South => Some(GamepadButton::South),
+        Button::East => Some(GamepadButton::East),
+        Button::North => Some(GamepadButton::North),
+        Button::West => Some(GamepadButton::West),
+        Button::Start => Some(GamepadButton::Start),
+        Button::Select => Some(GamepadButton::Select),
+        Button::LeftTrigger => Some(GamepadButton::LeftTrigger),
+        Button::RightTrigger => Some(GamepadButton::RightTrigger),
+        _ => None,
+    }
+}
+
+fn get_axis(axis: Axis) -> Option<GamepadAxis> {
+    match axis {
+        Axis::LeftStickX => Some(GamepadAxis::LeftStickX),
+        Axis::LeftStickY => Some(GamepadAxis::LeftStickY),
+        _ => None,
+    }
+}
@@ -0,0 +1,346 @@
+use crate::apu::filter::AudioFilter;
+use crate::apu::Apu;
+use crate::cartridge::Cartridge;
+use crate::cpu::Cpu;
+use crate::pacing::DOTS_PER_FRAME;
+
+pub const HEADER_SIZE: usize = 0x70;
+const MIN_LOAD_ADDRESS: u16 = 0x400;
+const RAM_SIZE: usize = 0x2000;
+const VBLANK_VECTOR: usize = 0x40;
+const TIMER_VECTOR: usize = 0x50;
+// The cpu waits for the next interrupt here between two calls of play.
+const IDLE_LOOP: u16 = 0x60;
+const IDLE_LOOP_CODE: [u8; 4] = [0xFB, 0x76, 0x18, 0xFC];
+const CYCLES_PER_FRAME: u128 = DOTS_PER_FRAME as u128 / 4;
+
+#[derive(Debug)]
+pub struct GbsHeader {
+    pub song_count: u8,
+    pub first_song: u8,
+    pub load: u16,
+    pub init: u16,
+    pub play: u16,
+    pub stack: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+impl GbsHeader {
+    pub fn parse(data: &[u8]) -> Result<GbsHeader, String> {
+        if data.len() < HEADER_SIZE || !is_gbs(data) {
+            return Err(String::from("not a GBS file"));
+        }
+        if data[3] != 1 {
+            return Err(format!("unsupported GBS version {}", data[3]));
+        }
+        let word = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+        let header = GbsHeader {
+            song_count: data[4],
+            first_song: data[5].max(1) - 1,
+            load: word(6),
+            init: word(8),
+            play: word(0xA),
+            stack: word(0xC),
+            timer_modulo: data[0xE],
+            timer_control: data[0xF],
+            title: get_text(&data[0x10..0x30]),
+            author: get_text(&data[0x30..0x50]),
+            copyright: get_text(&data[0x50..0x70]),
+        };
+        if header.song_count == 0 {
+            return Err(String::from("the GBS file has no song"));
+        }
+        if header.load < MIN_LOAD_ADDRESS || header.load >= 0x8000 {
+            return Err(format!("invalid GBS load address {:04x}", header.load));
+        }
+        Ok(header)
+    }
+
+    pub fn uses_timer(&self) -> bool {
+        self.timer_control & 0b100 != 0
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.timer_control & 0x80 != 0
+    }
+}
+
+pub fn is_gbs(data: &[u8]) -> bool {
+    data.starts_with(b"GBS")
+}
+
+fn get_text(data: &[u8]) -> String {
+    let end = data
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+// Runs a GBS rip on the cpu, timer and APU. The data is loaded at its address
+// in a banked cartridge, the RST vectors jump to the load address as the
+// format asks, and play is called from the VBlank or the timer interrupt.
+pub struct GbsPlayer {
+    header: GbsHeader,
+    image: Vec<u8>,
+    cpu: Cpu,
+    song: u8,
+    sample_rate: u32,
+    filter: AudioFilter,
+    frame_start: u128,
+}
+
+impl GbsPlayer {
+    pub fn new(data: &[u8], sample_rate: u32) -> Result<GbsPlayer, String> {
+        let header = GbsHeader::parse(data)?;
+        let image = get_image(&header, &data[HEADER_SIZE..]);
+        let song = header.first_song.min(header.song_count - 1);
+        let mut player = GbsPlayer {
+            cpu: Cpu::new(),
+            header,
+            image,
+            song,
+            sample_rate,
+            filter: AudioFilter::Dmg,
+            frame_start: 0,
+        };
+        player.play_song(song);
+        Ok(player)
+    }
+
+    pub fn header(&self) -> &GbsHeader {
+        &self.header
+    }
+
+    pub fn song(&self) -> u8 {
+        self.song
+    }
+
+    pub fn next_song(&mut self) {
+        self.play_song((self.song + 1) % self.header.song_count);
+    }
+
+    pub fn previous_song(&mut self) {
+        let song = match self.song {
+            0 => self.header.song_count - 1,
+            song => song - 1,
+        };
+        self.play_song(song);
+    }
+
+    pub fn play_song(&mut self, song: u8) {
+        self.song = song.min(self.header.song_count - 1);
+        let cartridge = Cartridge::with_rom_banks(&self.image, RAM_SIZE);
+        let mut cpu = Cpu::with_cartridge(cartridge, false);
+        let memory = &mut cpu.memory;
+        memory.write(0xFF40, 0x00);
+        memory.write(0xFF26, 0x80);
+        memory.write(0xFF25, 0xFF);
+        memory.write(0xFF24, 0x77);
+        memory.apu.set_sample_rate(self.sample_rate);
+        memory.apu.set_filter(self.filter);
+        memory.write(0xFF05, self.header.timer_modulo);
+        memory.write(0xFF06, self.header.timer_modulo);
+        memory.write(0xFF07, self.header.timer_control);
+        memory.double_speed = self.header.is_double_speed();
+        memory.write(0xFFFF, if self.header.uses_timer() { 0b100 } else { 0b1 });
+
+        // init returns to the idle loop, which enables the interrupts.
+        cpu.reg.sp = self.header.stack.wrapping_sub(2);
+        let [low, high] = IDLE_LOOP.to_le_bytes();
+        cpu.memory.write(cpu.reg.sp, low);
+        cpu.memory.write(cpu.reg.sp.wrapping_add(1), high);
+        cpu.reg.a = self.song;
+        cpu.memory.pc = self.header.init;
+        self.frame_start = cpu.memory.cycle;
+        self.cpu = cpu;
+    }
+
+    pub fn run_frame(&mut self) {
+        let budget = if self.cpu.memory.double_speed {
+            CYCLES_PER_FRAME * 2
+        } else {
+            CYCLES_PER_FRAME
+        };
+        while self.cpu.memory.cycle - self.frame_start < budget {
+            self.cpu.step();
+        }
+        self.frame_start += budget;
+        if !self.header.uses_timer() {
+            self.cpu.memory.interrupt.require_vblank();
+        }
+    }
+
+    pub fn cycles(&self) -> u128 {
+        self.cpu.memory.cycle * 4
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.cpu.memory.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.cpu.memory.apu
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Kept for the next songs, which start on a fresh cpu.
+    pub fn set_filter(&mut self, filter: AudioFilter) {
+        self.filter = filter;
+        self.cpu.memory.apu.set_filter(filter);
+    }
+
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.cpu.memory.apu.take_samples()
+    }
+
+    pub fn set_record_channels(&mut self, record_channels: bool) {
+        self.cpu.memory.apu.set_record_channels(record_channels);
+    }
+
+    pub fn channel_samples(&mut self) -> Vec<[f32; 4]> {
+        self.cpu.memory.apu.take_channel_samples()
+    }
+}
+
+fn get_image(header: &GbsHeader, data: &[u8]) -> Vec<u8> {
+    let load = header.load as usize;
+    let mut image = vec![0; load];
+    image.extend_from_slice(data);
+    for vector in (0..VBLANK_VECTOR).step_by(8) {
+        let [low, high] = header.load.wrapping_add(vector as u16).to_le_bytes();
+        image[vector..vector + 3].copy_from_slice(&[0xC3, low, high]);
+    }
+    let [low, high] = header.play.to_le_bytes();
+    for vector in [VBLANK_VECTOR, TIMER_VECTOR] {
+        image[vector..vector + 4].copy_from_slice(&[0xCD, low, high, 0xD9]);
+    }
+    let idle = IDLE_LOOP as usize;
+    image[idle..idle + IDLE_LOOP_CODE.len()].copy_from_slice(&IDLE_LOOP_CODE);
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // init stores the song number in C000, play counts its calls in C001.
+    const INIT: [u8; 4] = [0xEA, 0x00, 0xC0, 0xC9];
+    const PLAY: [u8; 8] = [0xFA, 0x01, 0xC0, 0x3C, 0xEA, 0x01, 0xC0, 0xC9];
+
+    fn get_gbs(timer_modulo: u8, timer_control: u8, code: &[u8]) -> Vec<u8> {
+        let mut gbs = vec![0; HEADER_SIZE];
+        gbs[..4].copy_from_slice(b"GBS\x01");
+        gbs[4] = 3;
+        gbs[5] = 2;
+        gbs[6..8].copy_from_slice(&0x400u16.to_le_bytes());
+        gbs[8..10].copy_from_slice(&0x400u16.to_le_bytes());
+        gbs[10..12].copy_from_slice(&0x404u16.to_le_bytes());
+        gbs[12..14].copy_from_slice(&0xFFFEu16.to_le_bytes());
+        gbs[14] = timer_modulo;
+        gbs[15] = timer_control;
+        gbs[0x10..0x15].copy_from_slice(b"Title");
+        gbs.extend_from_slice(code);
+        gbs
+    }
+
+    fn get_program() -> Vec<u8> {
+        [INIT.as_slice(), PLAY.as_slice()].concat()
+    }
+
+    #[test]
+    fn it_should_parse_header() {
+        let header = GbsHeader::parse(&get_gbs(0, 0, &get_program())).unwrap();
+
+        assert_eq!(header.song_count, 3);
+        assert_eq!(header.first_song, 1);
+        assert_eq!(header.load, 0x400);
+        assert_eq!(header.play, 0x404);
+        assert_eq!(header.title, "Title");
+        assert!(!header.uses_timer());
+    }
+
+    #[test]
+    fn it_should_refuse_invalid_files() {
+        let mut gbs = get_gbs(0, 0, &[]);
+        gbs[6..8].copy_from_slice(&0x100u16.to_le_bytes());
+
+        assert!(GbsHeader::parse(b"GBC\x01").is_err());
+        assert!(GbsHeader::parse(&gbs).is_err());
+    }
+
+    #[test]
+    fn it_should_init_song_and_play_on_vblank() {
+        let mut player = GbsPlayer::new(&get_gbs(0, 0, &get_program()), 44_100).unwrap();
+
+        for _ in 0..10 {
+            player.run_frame();
+        }
+
+        assert_eq!(player.cpu.memory.read(0xC000), 1);
+        assert_eq!(player.cpu.memory.read(0xC001), 9);
+        assert!(!player.audio_samples().is_empty());
+    }
+
+    #[test]
+    fn it_should_play_on_timer_interrupt() {
+        // 4096 Hz divided by 64, 64 calls per second.
+        let mut player = GbsPlayer::new(&get_gbs(0xC0, 0x04, &get_program()), 44_100).unwrap();
+
+        for _ in 0..60 {
+            player.run_frame();
+        }
+
+        let calls = player.cpu.memory.read(0xC001);
+        assert!((63..=65).contains(&calls), "{}", calls);
+    }
+
+    #[test]
+    fn it_should_change_song() {
+        let mut player = GbsPlayer::new(&get_gbs(0, 0, &get_program()), 44_100).unwrap();
+
+        player.next_song();
+        player.next_song();
+        player.run_frame();
+        assert_eq!(player.song(), 0);
+        assert_eq!(player.cpu.memory.read(0xC000), 0);
+        player.previous_song();
+
+        assert_eq!(player.song(), 2);
+    }
+
+    #[test]
+    fn it_should_step_back_through_many_songs() {
+        let mut gbs = get_gbs(0, 0, &get_program());
+        gbs[4] = 200;
+        let mut player = GbsPlayer::new(&gbs, 44_100).unwrap();
+
+        player.play_song(100);
+        player.previous_song();
+
+        assert_eq!(player.song(), 99);
+    }
+
+    #[test]
+    fn it_should_switch_rom_banks() {
+        // init selects bank 2 and copies its first byte to C000.
+        let mut program = vec![0x3E, 0x02, 0xEA, 0x00, 0x20, 0xFA, 0x00, 0x40];
+        program.extend_from_slice(&[0xEA, 0x00, 0xC0, 0xC9]);
+        program.resize(0x8000 - 0x400, 0);
+        program.push(0x5A);
+        let mut gbs = get_gbs(0, 0, &program);
+        gbs[10..12].copy_from_slice(&0x40Bu16.to_le_bytes());
+        let mut player = GbsPlayer::new(&gbs, 44_100).unwrap();
+
+        player.run_frame();
+
+        assert_eq!(player.cpu.memory.read(0xC000), 0x5A);
+    }
+}
@@ -0,0 +1,140 @@
+#![allow(clippy::new_without_default)]
+
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
+
+pub const BLOCK_SIZE: u16 = 0x10;
+
+pub struct Hdma {
+    pub source: u16,
+    pub destination: u16,
+    remaining: u8,
+    hblank: bool,
+}
+
+impl Hdma {
+    pub fn new() -> Hdma {
+        Hdma {
+            source: 0,
+            destination: 0x8000,
+            remaining: 0,
+            hblank: false,
+        }
+    }
+
+    pub fn write(&mut self, loc: u16, value: u8) {
+        match loc {
+            0xFF51 => self.source = (self.source & 0x00FF) | (value as u16) << 8,
+            0xFF52 => self.source = (self.source & 0xFF00) | (value & 0xF0) as u16,
+            0xFF53 => {
+                self.destination =
+                    0x8000 | (self.destination & 0x00FF) | ((value & 0x1F) as u16) << 8
+            }
+            0xFF54 => self.destination = (self.destination & 0xFF00) | (value & 0xF0) as u16,
+            _ => {}
+        }
+    }
+
+    pub fn read_control(&self) -> u8 {
+        if self.remaining == 0 {
+            return 0xFF;
+        }
+        let length = (self.remaining - 1) & 0x7F;
+        if self.hblank {
+            length
+        } else {
+            length | 0x80
+        }
+    }
+
+    pub fn write_control(&mut self, value: u8) -> u8 {
+        if self.hblank && value & 0x80 == 0 {
+            self.hblank = false;
+            return 0;
+        }
+        self.remaining = (value & 0x7F) + 1;
+        self.hblank = value & 0x80 != 0;
+        if self.hblank {
+            0
+        } else {
+            std::mem::take(&mut self.remaining)
+        }
+    }
+
+    pub fn is_hblank_active(&self) -> bool {
+        self.hblank
+    }
+
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let retval = (self.source, self.destination);
+        self.source = self.source.wrapping_add(BLOCK_SIZE);
+        self.destination = 0x8000 | (self.destination.wrapping_add(BLOCK_SIZE) & 0x1FFF);
+        retval
+    }
+
+    pub fn finish_hblank_block(&mut self) {
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.hblank = false;
+        }
+    }
+}
+
+impl Saveable for Hdma {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.source);
+        state.write_u16(self.destination);
+        state.write_u8(self.remaining);
+        state.write_bool(self.hblank);
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.source = state.read_u16()?;
+        self.destination = state.read_u16()?;
+        self.remaining = state.read_u8()?;
+        self.hblank = state.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_return_general_purpose_length() {
+        let mut hdma = Hdma::new();
+
+        assert_eq!(hdma.write_control(0x03), 4);
+        assert_eq!(hdma.read_control(), 0xFF);
+    }
+
+    #[test]
+    fn it_should_count_down_hblank_blocks() {
+        let mut hdma = Hdma::new();
+        hdma.write(0xFF51, 0xC1);
+        hdma.write(0xFF52, 0x2F);
+        hdma.write(0xFF53, 0xFF);
+        hdma.write(0xFF54, 0x00);
+
+        assert_eq!(hdma.write_control(0x81), 0);
+        assert_eq!(hdma.next_block(), (0xC120, 0x9F00));
+        hdma.finish_hblank_block();
+        assert_eq!(hdma.read_control(), 0x00);
+        assert_eq!(hdma.next_block(), (0xC130, 0x9F10));
+        hdma.finish_hblank_block();
+
+        assert!(!hdma.is_hblank_active());
+        assert_eq!(hdma.read_control(), 0xFF);
+    }
+
+    #[test]
+    fn it_should_cancel_hblank_transfer() {
+        let mut hdma = Hdma::new();
+        hdma.write_control(0x85);
+
+        hdma.write_control(0x00);
+
+        assert!(!hdma.is_hblank_active());
+        assert_eq!(hdma.read_control(), 0x85);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::joypad::Buttons;
+
+#[derive(Default)]
+pub struct InputScript {
+    events: Vec<(u64, Buttons)>,
+}
+
+impl InputScript {
+    pub fn parse(script: &str) -> Result<InputScript, String> {
+        let mut input = InputScript::default();
+        for (index, line) in script.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            input
+                .parse_line(line)
+                .map_err(|err| format!("line {}: {}", index + 1, err))?;
+        }
+        Ok(input)
+    }
+
+    // A frame number followed by the buttons pressed from that frame on.
+    pub fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let mut words = line.split_whitespace();
+        let frame = words
+            .next()
+            .and_then(|word| word.parse::<u64>().ok())
+            .ok_or_else(|| String::from("expected a frame number"))?;
+        if self.events.last().is_some_and(|(last, _)| *last >= frame) {
+            return Err(String::from("frames must be increasing"));
+        }
+        self.events.push((frame, parse_buttons(words)?));
+        Ok(())
+    }
+
+    pub fn buttons_at(&self, frame: u64) -> Buttons {
+        let index = self.events.partition_point(|(start, _)| *start <= frame);
+        match index {
+            0 => Buttons::default(),
+            _ => self.events[index - 1].1,
+        }
+    }
+
+    // Only keeps the frames where the buttons change.
+    pub fn push(&mut self, frame: u64, buttons: Buttons) {
+        if self.buttons_at(frame) == buttons {
+            return;
+        }
+        self.events.retain(|(start, _)| *start < frame);
+        self.events.push((frame, buttons));
+    }
+
+    pub fn to_text(&self) -> String {
+        self.events
+            .iter()
+            .map(|(frame, buttons)| format!("{} {}\n", frame, format_buttons(buttons)))
+            .collect()
+    }
+}
+
+pub fn parse_buttons<'a>(names: impl Iterator<Item = &'a str>) -> Result<Buttons, String> {
+    let mut buttons = Buttons::default();
+    for name in names {
+        match name {
+            "-" => {}
+            "right" => buttons.right = true,
+            "left" => buttons.left = true,
+            "up" => buttons.up = true,
+            "down" => buttons.down = true,
+            "a" => buttons.a = true,
+            "b" => buttons.b = true,
+            "select" => buttons.select = true,
+            "start" => buttons.start = true,
+            _ => return Err(format!("unknown button {}", name)),
+        }
+    }
+    Ok(buttons)
+}
+
+pub fn format_buttons(buttons: &Buttons) -> String {
+    let names: Vec<&str> = [
+        (buttons.right, "right"),
+        (buttons.left, "left"),
+        (buttons.up, "up"),
+        (buttons.down, "down"),
+        (buttons.a, "a"),
+        (buttons.b, "b"),
+        (buttons.select, "select"),
+        (buttons.start, "start"),
+    ]
+    .iter()
+    .filter(|(pressed, _)| *pressed)
+    .map(|(_, name)| *name)
+    .collect();
+    match names.is_empty() {
+        true => String::from("-"),
+        false => names.join(" "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_hold_buttons_until_next_event() {
+        let script = InputScript::parse("# boot\n10 start\n12 -\n20 a right # jump\n").unwrap();
+
+        assert_eq!(script.buttons_at(0), Buttons::default());
+        assert!(script.buttons_at(10).start);
+        assert!(script.buttons_at(11).start);
+        assert_eq!(script.buttons_at(12), Buttons::default());
+        let buttons = script.buttons_at(500);
+        assert!(buttons.a && buttons.right && !buttons.start);
+    }
+
+    #[test]
+    fn it_should_write_pushed_changes() {
+        let mut script = InputScript::default();
+        let jump = Buttons {
+            a: true,
+            right: true,
+            ..Buttons::default()
+        };
+
+        script.push(1, Buttons::default());
+        script.push(5, jump);
+        script.push(6, jump);
+        script.push(9, Buttons::default());
+
+        assert_eq!(script.to_text(), "5 right a\n9 -\n");
+        assert!(
+            InputScript::parse(&script.to_text())
+                .unwrap()
+                .buttons_at(7)
+                .a
+        );
+    }
+
+    #[test]
+    fn it_should_refuse_invalid_scripts() {
+        assert!(InputScript::parse("start").is_err());
+        assert!(InputScript::parse("10 jump").is_err());
+        assert!(InputScript::parse("10 a\n5 b").is_err());
+    }
+}
@@ -1,20 +1,73 @@
 #![allow(clippy::new_without_default)]
 #![allow(dead_code)]
 
-use minifb::{Key, KeyRepeat, Window};
+use crate::pacing::FRAME_RATE;
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
+use std::ops::BitOr;
 
-#[derive(Clone, Debug)]
-enum Mode {
-    Pad,
-    Buttons,
-    None,
+pub const DEFAULT_TURBO_RATE: u32 = 10;
+pub const MAX_TURBO_RATE: u32 = 30;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Buttons {
+    pub right: bool,
+    pub left: bool,
+    pub up: bool,
+    pub down: bool,
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+}
+
+impl Buttons {
+    // Releases both directions of an axis held at once, which a real pad cannot do
+    // and some games do not expect.
+    pub fn without_opposing_directions(self) -> Buttons {
+        let horizontal = !(self.left && self.right);
+        let vertical = !(self.up && self.down);
+        Buttons {
+            right: self.right && horizontal,
+            left: self.left && horizontal,
+            up: self.up && vertical,
+            down: self.down && vertical,
+            ..self
+        }
+    }
 }
 
+// A button is pressed when any of the inputs presses it.
+impl BitOr for Buttons {
+    type Output = Buttons;
+
+    fn bitor(self, other: Buttons) -> Buttons {
+        Buttons {
+            right: self.right || other.right,
+            left: self.left || other.left,
+            up: self.up || other.up,
+            down: self.down || other.down,
+            a: self.a || other.a,
+            b: self.b || other.b,
+            select: self.select || other.select,
+            start: self.start || other.start,
+        }
+    }
+}
+
+// P1 selects the direction lines with bit 4 and the button lines with bit 5, a
+// line reads 0 when its key is pressed in a selected group.
 #[derive(Clone, Debug)]
 pub struct Joypad {
     pad: u8,
     buttons: u8,
-    mode: Mode,
+    select: u8,
+    block_opposing: bool,
+    input: Buttons,
+    pressed: Buttons,
+    turbo_a: bool,
+    turbo_b: bool,
+    turbo_rate: u32,
+    turbo_frame: u32,
     pub interrupt: bool,
 }
 
@@ -23,11 +76,50 @@ impl Joypad {
         Joypad {
             pad: 0xF,
             buttons: 0xF,
-            mode: Mode::None,
+            select: 0x30,
+            block_opposing: false,
+            input: Buttons::default(),
+            pressed: Buttons::default(),
+            turbo_a: false,
+            turbo_b: false,
+            turbo_rate: DEFAULT_TURBO_RATE,
+            turbo_frame: 0,
             interrupt: false,
         }
     }
 
+    pub fn set_block_opposing(&mut self, block_opposing: bool) {
+        self.block_opposing = block_opposing;
+    }
+
+    // Presses per second of the turbo buttons, a press lasting at least a frame.
+    pub fn set_turbo_rate(&mut self, turbo_rate: u32) {
+        self.turbo_rate = turbo_rate.clamp(1, MAX_TURBO_RATE);
+    }
+
+    // Held turbo buttons are pressed and released on their own every few frames,
+    // starting with a press.
+    pub fn set_turbo(&mut self, turbo_a: bool, turbo_b: bool) {
+        if !self.turbo_a && !self.turbo_b {
+            self.turbo_frame = 0;
+        }
+        self.turbo_a = turbo_a;
+        self.turbo_b = turbo_b;
+        self.update_buttons();
+    }
+
+    pub fn clock_frame(&mut self) {
+        if self.turbo_a || self.turbo_b {
+            self.turbo_frame = self.turbo_frame.wrapping_add(1);
+            self.update_buttons();
+        }
+    }
+
+    // The buttons seen by the game, with the turbo applied.
+    pub fn pressed(&self) -> Buttons {
+        self.pressed
+    }
+
     pub fn is_interrupt(&mut self) -> bool {
         if self.interrupt {
             self.interrupt = false;
@@ -37,66 +129,209 @@ impl Joypad {
     }
 
     pub fn set_joypad(&mut self, value: u8) {
-        if (value >> 4) & 1 == 0 {
-            self.mode = Mode::Pad
+        let before = self.get_lines();
+        self.select = value & 0x30;
+        self.update_interrupt(before);
+    }
+
+    pub fn get_joypad(&mut self) -> u8 {
+        0xC0 | self.select | self.get_lines()
+    }
+
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.input = buttons;
+        self.update_buttons();
+    }
+
+    fn is_turbo_pressed(&self) -> bool {
+        let half_presses = self.turbo_frame as f64 * self.turbo_rate as f64 * 2.0 / FRAME_RATE;
+        (half_presses as u64).is_multiple_of(2)
+    }
+
+    fn update_buttons(&mut self) {
+        let turbo = self.is_turbo_pressed();
+        let mut buttons = Buttons {
+            a: self.input.a || (self.turbo_a && turbo),
+            b: self.input.b || (self.turbo_b && turbo),
+            ..self.input
+        };
+        if self.block_opposing {
+            buttons = buttons.without_opposing_directions();
         }
-        if (value >> 5) & 1 == 0 {
-            self.mode = Mode::Buttons;
+        self.pressed = buttons;
+        let before = self.get_lines();
+        self.pad = get_nibble(buttons.right, buttons.left, buttons.up, buttons.down);
+        self.buttons = get_nibble(buttons.a, buttons.b, buttons.select, buttons.start);
+        self.update_interrupt(before);
+    }
+
+    fn get_lines(&self) -> u8 {
+        let mut lines = 0xF;
+        if self.select & 0x10 == 0 {
+            lines &= self.pad;
         }
-        if value & 0x30 == 0x30 {
-            self.mode = Mode::None;
+        if self.select & 0x20 == 0 {
+            lines &= self.buttons;
         }
+        lines
     }
 
-    pub fn get_joypad(&mut self) -> u8 {
-        let retval = 0xC0 + self.get_mode();
-        match self.mode {
-            Mode::Pad => retval | self.pad,
-            Mode::Buttons => retval | self.buttons,
-            Mode::None => retval | 0xF,
+    // Any selected line going from high to low requests the interrupt.
+    fn update_interrupt(&mut self, before: u8) {
+        if before & !self.get_lines() != 0 {
+            self.interrupt = true;
         }
     }
+}
 
-    fn get_mode(&mut self) -> u8 {
-        match self.mode {
-            Mode::Pad => 0x10,
-            Mode::Buttons => 0x20,
-            Mode::None => 0x0,
-        }
+fn get_nibble(bit0: bool, bit1: bool, bit2: bool, bit3: bool) -> u8 {
+    let pressed = bit0 as u8 | (bit1 as u8) << 1 | (bit2 as u8) << 2 | (bit3 as u8) << 3;
+    !pressed & 0xF
+}
+
+impl Saveable for Joypad {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.pad);
+        state.write_u8(self.buttons);
+        state.write_u8(self.select);
+        state.write_bool(self.interrupt);
+        state.write_u32(self.turbo_frame);
     }
 
-    pub fn update(&mut self, window: &Window) {
-        let before_pad = self.pad & 0xF;
-        let before_buttons = self.buttons & 0xF;
-        window
-            .get_keys_pressed(KeyRepeat::Yes)
-            .iter()
-            .for_each(|key| match key {
-                Key::J => self.buttons &= 0b1110,
-                Key::K => self.buttons &= 0b1101,
-                Key::I => self.buttons &= 0b1011,
-                Key::U => self.buttons &= 0b0111,
-
-                Key::D => self.pad &= 0b1110,
-                Key::A => self.pad &= 0b1101,
-                Key::W => self.pad &= 0b1011,
-                Key::S => self.pad &= 0b0111,
-                _ => {}
-            });
-        window.get_keys_released().iter().for_each(|key| match key {
-            Key::J => self.buttons |= 0b0001,
-            Key::K => self.buttons |= 0b0010,
-            Key::I => self.buttons |= 0b0100,
-            Key::U => self.buttons |= 0b1000,
-
-            Key::D => self.pad |= 0b0001,
-            Key::A => self.pad |= 0b0010,
-            Key::W => self.pad |= 0b1100,
-            Key::S => self.pad |= 0b1000,
-            _ => {}
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pad = state.read_u8()? & 0xF;
+        self.buttons = state.read_u8()? & 0xF;
+        self.select = state.read_u8()? & 0x30;
+        self.interrupt = state.read_bool()?;
+        self.turbo_frame = state.read_u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_read_pressed_pad() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(Buttons {
+            up: true,
+            left: true,
+            ..Buttons::default()
         });
-        if before_pad > self.pad || before_buttons > self.buttons {
-            self.interrupt = true;
+        joypad.set_joypad(0x20);
+
+        assert_eq!(joypad.get_joypad() & 0xF, 0b1001);
+    }
+
+    #[test]
+    fn it_should_release_only_one_direction() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(Buttons {
+            up: true,
+            down: true,
+            ..Buttons::default()
+        });
+        joypad.set_buttons(Buttons {
+            down: true,
+            ..Buttons::default()
+        });
+        joypad.set_joypad(0x20);
+
+        assert_eq!(joypad.get_joypad() & 0xF, 0b0111);
+    }
+
+    #[test]
+    fn it_should_read_pressed_buttons() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(Buttons {
+            a: true,
+            start: true,
+            ..Buttons::default()
+        });
+        joypad.set_joypad(0x10);
+
+        assert_eq!(joypad.get_joypad() & 0xF, 0b0110);
+        assert!(joypad.is_interrupt());
+    }
+
+    #[test]
+    fn it_should_combine_both_groups_when_selected() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(Buttons {
+            right: true,
+            b: true,
+            ..Buttons::default()
+        });
+
+        joypad.set_joypad(0x00);
+
+        assert_eq!(joypad.get_joypad(), 0xC0 | 0b1100);
+        joypad.set_joypad(0x20);
+        assert_eq!(joypad.get_joypad(), 0xE0 | 0b1110);
+    }
+
+    #[test]
+    fn it_should_interrupt_on_any_falling_selected_line() {
+        let mut joypad = Joypad::new();
+        joypad.set_joypad(0x20);
+        joypad.set_buttons(Buttons {
+            down: true,
+            ..Buttons::default()
+        });
+        assert!(joypad.is_interrupt());
+
+        // Up falls while Down rises, the nibble grows but a line still fell.
+        joypad.set_buttons(Buttons {
+            up: true,
+            ..Buttons::default()
+        });
+        assert!(joypad.is_interrupt());
+        joypad.set_buttons(Buttons {
+            up: true,
+            a: true,
+            ..Buttons::default()
+        });
+        assert!(!joypad.is_interrupt());
+        joypad.set_joypad(0x10);
+
+        assert!(joypad.is_interrupt());
+    }
+
+    #[test]
+    fn it_should_toggle_turbo_buttons_each_few_frames() {
+        let mut joypad = Joypad::new();
+        joypad.set_turbo_rate(15);
+        joypad.set_joypad(0x10);
+
+        joypad.set_turbo(true, false);
+        let mut presses = vec![];
+        for _ in 0..8 {
+            presses.push(joypad.get_joypad() & 1 == 0);
+            joypad.clock_frame();
         }
+
+        assert_eq!(
+            presses,
+            [true, true, false, false, true, true, false, false]
+        );
+        joypad.set_turbo(false, false);
+        assert!(!joypad.pressed().a);
+    }
+
+    #[test]
+    fn it_should_block_opposing_directions() {
+        let mut joypad = Joypad::new();
+        joypad.set_block_opposing(true);
+        joypad.set_buttons(Buttons {
+            left: true,
+            right: true,
+            up: true,
+            ..Buttons::default()
+        });
+        joypad.set_joypad(0x20);
+
+        assert_eq!(joypad.get_joypad() & 0xF, 0b1011);
     }
 }
@@ -0,0 +1,299 @@
+use game_boy::joypad::Buttons;
+use minifb::{Key, KeyRepeat, Window};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+    TurboA,
+    TurboB,
+    Save(u8),
+    Load(u8),
+    Rewind,
+    FastForward,
+    FastForwardToggle,
+    SlowMotion,
+    Pause,
+    FrameAdvance,
+    Palette,
+    Record,
+    Screenshot,
+    Mute(u8),
+    NextSong,
+    PreviousSong,
+    Quit,
+}
+
+// Names used in the config file and by --bind, with the default keys.
+const ACTIONS: [(&str, Action, &[Key]); 34] = [
+    ("right", Action::Right, &[Key::D]),
+    ("left", Action::Left, &[Key::A]),
+    ("up", Action::Up, &[Key::W]),
+    ("down", Action::Down, &[Key::S]),
+    ("a", Action::A, &[Key::J]),
+    ("b", Action::B, &[Key::K]),
+    ("select", Action::Select, &[Key::I]),
+    ("start", Action::Start, &[Key::U]),
+    ("turbo_a", Action::TurboA, &[Key::H]),
+    ("turbo_b", Action::TurboB, &[Key::L]),
+    ("save1", Action::Save(1), &[Key::F1]),
+    ("save2", Action::Save(2), &[Key::F2]),
+    ("save3", Action::Save(3), &[Key::F3]),
+    ("save4", Action::Save(4), &[Key::F4]),
+    ("load1", Action::Load(1), &[Key::F5]),
+    ("load2", Action::Load(2), &[Key::F6]),
+    ("load3", Action::Load(3), &[Key::F7]),
+    ("load4", Action::Load(4), &[Key::F8]),
+    ("rewind", Action::Rewind, &[Key::Backspace]),
+    ("fast_forward", Action::FastForward, &[Key::Tab]),
+    ("fast_forward_toggle", Action::FastForwardToggle, &[Key::T]),
+    ("slow_motion", Action::SlowMotion, &[Key::M]),
+    ("pause", Action::Pause, &[Key::P]),
+    ("frame_advance", Action::FrameAdvance, &[Key::N]),
+    ("palette", Action::Palette, &[Key::C]),
+    ("record", Action::Record, &[Key::R]),
+    ("screenshot", Action::Screenshot, &[Key::F12]),
+    ("mute1", Action::Mute(1), &[Key::Key1]),
+    ("mute2", Action::Mute(2), &[Key::Key2]),
+    ("mute3", Action::Mute(3), &[Key::Key3]),
+    ("mute4", Action::Mute(4), &[Key::Key4]),
+    ("next_song", Action::NextSong, &[Key::Right, Key::D]),
+    ("previous_song", Action::PreviousSong, &[Key::Left, Key::A]),
+    ("quit", Action::Quit, &[Key::Escape]),
+];
+
+// Keys that can be bound, named as minifb names them, Key1 also being 1.
+const KEYS: [Key; 106] = [
+    Key::Key0,
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Key4,
+    Key::Key5,
+    Key::Key6,
+    Key::Key7,
+    Key::Key8,
+    Key::Key9,
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+    Key::F13,
+    Key::F14,
+    Key::F15,
+    Key::Down,
+    Key::Left,
+    Key::Right,
+    Key::Up,
+    Key::Apostrophe,
+    Key::Backquote,
+    Key::Backslash,
+    Key::Comma,
+    Key::Equal,
+    Key::LeftBracket,
+    Key::Minus,
+    Key::Period,
+    Key::RightBracket,
+    Key::Semicolon,
+    Key::Slash,
+    Key::Backspace,
+    Key::Delete,
+    Key::End,
+    Key::Enter,
+    Key::Escape,
+    Key::Home,
+    Key::Insert,
+    Key::Menu,
+    Key::PageDown,
+    Key::PageUp,
+    Key::Pause,
+    Key::Space,
+    Key::Tab,
+    Key::NumLock,
+    Key::CapsLock,
+    Key::ScrollLock,
+    Key::LeftShift,
+    Key::RightShift,
+    Key::LeftCtrl,
+    Key::RightCtrl,
+    Key::NumPad0,
+    Key::NumPad1,
+    Key::NumPad2,
+    Key::NumPad3,
+    Key::NumPad4,
+    Key::NumPad5,
+    Key::NumPad6,
+    Key::NumPad7,
+    Key::NumPad8,
+    Key::NumPad9,
+    Key::NumPadDot,
+    Key::NumPadSlash,
+    Key::NumPadAsterisk,
+    Key::NumPadMinus,
+    Key::NumPadPlus,
+    Key::NumPadEnter,
+    Key::LeftAlt,
+    Key::RightAlt,
+    Key::LeftSuper,
+    Key::RightSuper,
+];
+
+// The keys of each button and hotkey, several keys can trigger the same action.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyBindings {
+    bindings: Vec<(Action, Vec<Key>)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        KeyBindings {
+            bindings: ACTIONS
+                .iter()
+                .map(|(_, action, keys)| (*action, keys.to_vec()))
+                .collect(),
+        }
+    }
+}
+
+impl KeyBindings {
+    // Replaces the keys of an action, given as names separated by spaces or
+    // commas. No key leaves the action unbound.
+    pub fn bind(&mut self, action: &str, keys: &str) -> Result<(), String> {
+        let action = parse_action(action)?;
+        let keys = keys
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|name| !name.is_empty())
+            .map(parse_key)
+            .collect::<Result<Vec<Key>, String>>()?;
+        match self.bindings.iter_mut().find(|(bound, _)| *bound == action) {
+            Some((_, bound_keys)) => *bound_keys = keys,
+            None => self.bindings.push((action, keys)),
+        }
+        Ok(())
+    }
+
+    pub fn keys(&self, action: Action) -> &[Key] {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == action)
+            .map_or(&[], |(_, keys)| keys)
+    }
+
+    pub fn is_down(&self, window: &Window, action: Action) -> bool {
+        self.keys(action).iter().any(|key| window.is_key_down(*key))
+    }
+
+    pub fn is_pressed(&self, window: &Window, action: Action) -> bool {
+        self.keys(action)
+            .iter()
+            .any(|key| window.is_key_pressed(*key, KeyRepeat::No))
+    }
+
+    pub fn is_repeated(&self, window: &Window, action: Action) -> bool {
+        self.keys(action)
+            .iter()
+            .any(|key| window.is_key_pressed(*key, KeyRepeat::Yes))
+    }
+}
+
+pub fn parse_action(name: &str) -> Result<Action, String> {
+    ACTIONS
+        .iter()
+        .find(|(action, _, _)| *action == name)
+        .map(|(_, action, _)| *action)
+        .ok_or_else(|| format!("unknown action {}", name))
+}
+
+fn parse_key(name: &str) -> Result<Key, String> {
+    let name = name.to_lowercase();
+    KEYS.iter()
+        .find(|key| {
+            let key_name = format!("{:?}", key).to_lowercase();
+            key_name == name || key_name.strip_prefix("key") == Some(&name)
+        })
+        .copied()
+        .ok_or_else(|| format!("unknown key {}", name))
+}
+
+pub fn read_buttons(window: &Window, keys: &KeyBindings) -> Buttons {
+    Buttons {
+        right: keys.is_down(window, Action::Right),
+        left: keys.is_down(window, Action::Left),
+        up: keys.is_down(window, Action::Up),
+        down: keys.is_down(window, Action::Down),
+        a: keys.is_down(window, Action::A),
+        b: keys.is_down(window, Action::B),
+        select: keys.is_down(window, Action::Select),
+        start: keys.is_down(window, Action::Start),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_bind_several_keys() {
+        let mut keys = KeyBindings::default();
+
+        keys.bind("a", "Z, space").unwrap();
+        keys.bind("save1", "1").unwrap();
+        keys.bind("mute1", "F9").unwrap();
+        keys.bind("pause", "").unwrap();
+
+        assert_eq!(keys.keys(Action::A), [Key::Z, Key::Space]);
+        assert_eq!(keys.keys(Action::Save(1)), [Key::Key1]);
+        assert_eq!(keys.keys(Action::Mute(1)), [Key::F9]);
+        assert!(keys.keys(Action::Pause).is_empty());
+        assert_eq!(keys.keys(Action::Quit), [Key::Escape]);
+    }
+
+    #[test]
+    fn it_should_refuse_unknown_names() {
+        let mut keys = KeyBindings::default();
+
+        assert!(keys.bind("jump", "Space").is_err());
+        assert!(keys.bind("a", "Hyper").is_err());
+        assert!(keys.bind("a", "Unknown").is_err());
+    }
+}
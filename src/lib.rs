@@ -0,0 +1,19 @@
+pub mod apu;
+pub mod audio;
+pub mod cartridge;
+pub mod cpu;
+pub mod debug_tools;
+pub mod emulator;
+pub mod gbs;
+pub mod hdma;
+pub mod input_script;
+pub mod joypad;
+pub mod memorybus;
+pub mod movie;
+pub mod pacing;
+pub mod png;
+pub mod ppu;
+pub mod rewind;
+pub mod savestate;
+pub mod serial;
+pub mod wav;
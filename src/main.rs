@@ -1,33 +1,137 @@
-use crate::ppu::config::{DEBUG_HEIGHT, DEBUG_WIDTH, HEIGHT, WIDTH};
-use debug_tools::DEBUG_SPRITES;
-use gameboy::run_gameboy;
+use cli::{parse_args, Command, Options, USAGE};
+use config::{load_config, Config};
+use game_boy::apu::debug_view::{AUDIO_DEBUG_HEIGHT, AUDIO_DEBUG_WIDTH};
+use game_boy::ppu::config::{DEBUG_HEIGHT, DEBUG_WIDTH, HEIGHT, WIDTH};
+use gameboy::{build_emulator, load_movie, run_gameboy, run_headless};
 use minifb::{Scale, ScaleMode, Window, WindowOptions};
+use music::{is_music_file, load_player, run_music, run_music_headless};
+use sound_debug::AudioWindow;
+use std::{env, process};
 
-pub mod apu;
-pub mod cartridge;
-pub mod cpu;
-pub mod debug_tools;
-pub mod gameboy;
-pub mod joypad;
-pub mod memorybus;
-pub mod ppu;
+#[cfg(feature = "audio")]
+mod audio_device;
+mod cli;
+mod config;
+mod gameboy;
+mod gamepad;
+#[cfg(feature = "gamepad")]
+mod gamepad_device;
+mod keyboard;
+mod music;
+mod recorder;
+mod sound_debug;
+mod speed;
 
 fn main() {
-    let mut window = get_window();
-    let mut debug_window = get_debug_window();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            print!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("Error: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    let config = match get_config(&options) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    };
+    if is_music_file(&options.rom) {
+        play_music(&options, &config);
+        return;
+    }
+    let emulator = match build_emulator(&options, &config) {
+        Ok(emulator) => emulator,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    };
+    let movie = match load_movie(&options, &emulator) {
+        Ok(movie) => movie,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    };
+    if options.headless {
+        if let Err(err) = run_headless(emulator, &options, movie) {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+    println!("{}", emulator.header());
+    let mut window = get_window(options.scale);
+    let mut debug_window = options
+        .debug
+        .then(|| get_debug_window("Debug gameboy", DEBUG_WIDTH, DEBUG_HEIGHT, 0));
+    let mut audio_window = get_audio_window(options.debug_audio);
     window.update();
     if let Some(ref mut w) = debug_window {
         w.update();
     }
-    run_gameboy(&mut window, &mut debug_window);
+    run_gameboy(
+        emulator,
+        &options,
+        &config,
+        &mut window,
+        &mut debug_window,
+        &mut audio_window,
+        movie,
+    );
+}
+
+fn get_config(options: &Options) -> Result<Config, String> {
+    let mut config = load_config(options.config.as_deref())?;
+    for (action, keys) in &options.bindings {
+        config
+            .keys
+            .bind(action, keys)
+            .map_err(|err| format!("--bind {}={}: {}", action, keys, err))?;
+    }
+    Ok(config)
+}
+
+fn play_music(options: &Options, config: &Config) {
+    let player = match load_player(options) {
+        Ok(player) => player,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    };
+    if options.headless {
+        if let Err(err) = run_music_headless(player, options) {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+    let mut window = get_window(options.scale);
+    let mut audio_window = get_audio_window(options.debug_audio);
+    run_music(
+        player,
+        options,
+        &config.keys,
+        &mut window,
+        &mut audio_window,
+    );
 }
-fn get_window() -> Window {
+
+fn get_window(scale: u8) -> Window {
     let options = WindowOptions {
         borderless: false,
         transparency: false,
         title: true,
         resize: false,
-        scale: Scale::X4,
+        scale: get_scale(scale),
         scale_mode: ScaleMode::Stretch,
         topmost: true,
         none: false,
@@ -39,10 +143,27 @@ fn get_window() -> Window {
     window
 }
 
-fn get_debug_window() -> Option<Window> {
-    if !DEBUG_SPRITES {
-        return None;
+fn get_scale(scale: u8) -> Scale {
+    match scale {
+        1 => Scale::X1,
+        2 => Scale::X2,
+        8 => Scale::X8,
+        _ => Scale::X4,
     }
+}
+
+fn get_audio_window(debug_audio: bool) -> Option<AudioWindow> {
+    debug_audio.then(|| {
+        AudioWindow::new(get_debug_window(
+            "Sound debug gameboy",
+            AUDIO_DEBUG_WIDTH,
+            AUDIO_DEBUG_HEIGHT,
+            (DEBUG_HEIGHT * 2 + 40) as isize,
+        ))
+    })
+}
+
+fn get_debug_window(title: &str, width: usize, height: usize, y: isize) -> Window {
     let options = WindowOptions {
         borderless: false,
         transparency: false,
@@ -53,10 +174,10 @@ fn get_debug_window() -> Option<Window> {
         topmost: true,
         none: false,
     };
-    let mut window = Window::new("Debug gameboy", DEBUG_WIDTH, DEBUG_HEIGHT, options)
-        .expect("Error while creating window");
+    let mut window =
+        Window::new(title, width, height, options).expect("Error while creating window");
     window.topmost(true);
-    window.set_position(0, 0);
+    window.set_position(0, y);
     window.set_background_color(255, 0, 0);
-    Some(window)
+    window
 }
@@ -6,12 +6,17 @@ use crate::cartridge::Cartridge;
 use crate::cpu::interrupt::Interrupt;
 use crate::cpu::registers::{combine, split_u16};
 use crate::cpu::timer::Timer;
+use crate::hdma::{Hdma, BLOCK_SIZE};
 use crate::joypad::Joypad;
 use crate::ppu::Ppu;
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
+use crate::serial::Serial;
 
 const VRAM_SIZE: u16 = 0x9FFF - 0x8000 + 1;
 const HRAM_SIZE: u16 = 0xFFFE - 0xFF80 + 1;
 const WRAM_SIZE: u16 = 0xDFFF - 0xC000 + 1;
+const WRAM_BANK_SIZE: usize = 0x1000;
+const WRAM_BANKS: usize = 8;
 const MEM_MAX: u16 = 0xFFFF;
 
 pub struct MemoryBus {
@@ -20,14 +25,19 @@ pub struct MemoryBus {
     pub interrupt: Interrupt,
     pub pc: u16,
     pub cycle: u128,
-    apu: Apu,
+    pub apu: Apu,
+    pub cartridge: Cartridge,
+    pub serial: Serial,
+    pub double_speed: bool,
+    cgb: bool,
+    prepare_speed_switch: bool,
+    wram_bank: u8,
+    hdma: Hdma,
     timer: Timer,
-    cartridge: Cartridge,
     vram: [u8; VRAM_SIZE as usize],
     hram: [u8; HRAM_SIZE as usize],
-    wram: [u8; WRAM_SIZE as usize],
+    wram: [u8; WRAM_BANK_SIZE * WRAM_BANKS],
     ie: u8,
-    debug: [u8; 2],
 
     dma: bool,
     dma_addr: u16,
@@ -36,20 +46,31 @@ pub struct MemoryBus {
 
 impl MemoryBus {
     pub fn new() -> MemoryBus {
+        MemoryBus::with_cartridge(Cartridge::new(&[], None), false)
+    }
+
+    pub fn with_cartridge(cartridge: Cartridge, cgb: bool) -> MemoryBus {
+        let mut ppu = Ppu::new();
+        ppu.set_cgb(cgb);
         MemoryBus {
-            ppu: Ppu::new(),
+            ppu,
             apu: Apu::new(),
-            cartridge: Cartridge::new(),
+            cartridge,
+            serial: Serial::new(),
+            double_speed: false,
+            cgb,
+            prepare_speed_switch: false,
+            wram_bank: 1,
+            hdma: Hdma::new(),
             timer: Timer::new(),
             joypad: Joypad::new(),
             interrupt: Interrupt::new(),
             vram: [0; VRAM_SIZE as usize],
             hram: [0; HRAM_SIZE as usize],
-            wram: [0; WRAM_SIZE as usize],
+            wram: [0; WRAM_BANK_SIZE * WRAM_BANKS],
             pc: 0x0,
             ie: 0,
             cycle: 0,
-            debug: [0; 2],
             dma: false,
             dma_addr: 0,
             dma_target: 0xFE00,
@@ -60,27 +81,31 @@ impl MemoryBus {
         let loc = at & MEM_MAX;
         match loc {
             0..=0x7FFF => self.cartridge.read(loc),
+            0xA000..=0xBFFF => self.cartridge.read_ram(loc),
 
             0xFF00 => self.joypad.get_joypad(),
-            0xFF01 => self.debug[0],
-            0xFF02 => self.debug[1],
+            0xFF01 => self.serial.data,
+            0xFF02 => self.serial.read_control(),
             0xFF04 => (self.timer.div >> 8) as u8,
             0xFF05 => self.timer.tima,
             0xFF06 => self.timer.tma,
             0xFF07 => self.timer.tac,
             0xFF0F => self.interrupt.iflag,
+            0xFF10..=0xFF3F => self.apu.read(loc),
 
             0xFF40..=0xFF4B => self.ppu.read(loc as usize),
-            0xFF51..=0xFF55 => self.ppu.read(loc as usize),
+            0xFF4D if self.cgb => {
+                0x7E | ((self.double_speed as u8) << 7) | self.prepare_speed_switch as u8
+            }
+            0xFF4F => self.ppu.read(loc as usize),
+            0xFF55 if self.cgb => self.hdma.read_control(),
+            0xFF68..=0xFF6B => self.ppu.read(loc as usize),
+            0xFF70 if self.cgb => 0xF8 | self.wram_bank,
             0x8000..=0x9FFF => self.ppu.read(loc as usize),
             0xFE00..=0xFE9F => self.ppu.read(loc as usize),
 
             0xFF80..=0xFFFE => self.hram[(loc - 0xFF80) as usize],
-            0xC000..=0xDFFF => self.wram[(loc - 0xC000) as usize],
-            0xE000..=0xFDFF => {
-                let new_loc = loc & (WRAM_SIZE - 1);
-                self.wram[(new_loc) as usize]
-            }
+            0xC000..=0xFDFF => self.wram[self.get_wram_index(loc)],
             0xFFFF => self.interrupt.ie,
             _ => {
                 //eprintln!("Read: memory not handled: {:x}", loc);
@@ -94,14 +119,15 @@ impl MemoryBus {
 
         match loc {
             0..=0x7FFF => self.cartridge.write(at, value),
+            0xA000..=0xBFFF => self.cartridge.write_ram(loc, value),
 
             0xFF00 => self.joypad.set_joypad(value),
-            0xFF01 => self.debug[0] = value, //value,
-            0xFF02 => self.debug[1] = value, //value,
-            0xFF04 => self.timer.div = 0,
-            0xFF05 => self.timer.tima = value,
-            0xFF06 => self.timer.tma = value,
-            0xFF07 => self.timer.tac = value | 0xF8,
+            0xFF01 => self.serial.data = value,
+            0xFF02 => self.serial.write_control(value),
+            0xFF04 => self.timer.write_div(),
+            0xFF05 => self.timer.write_tima(value),
+            0xFF06 => self.timer.write_tma(value),
+            0xFF07 => self.timer.write_tac(value),
             0xFF0F => self.interrupt.set_iflag(value),
             0xFF46 => self.handle_dma(value),
             0xFF50 => {
@@ -112,15 +138,16 @@ impl MemoryBus {
             0xFF10..=0xFF3F => self.apu.write(loc, value),
 
             0xFF40..=0xFF4B => self.ppu.write(loc as usize, value),
-            0xFF51..=0xFF55 => self.ppu.write(loc as usize, value),
+            0xFF4D if self.cgb => self.prepare_speed_switch = value & 1 == 1,
+            0xFF4F => self.ppu.write(loc as usize, value),
+            0xFF51..=0xFF54 if self.cgb => self.hdma.write(loc, value),
+            0xFF55 if self.cgb => self.handle_hdma(value),
+            0xFF68..=0xFF6B => self.ppu.write(loc as usize, value),
+            0xFF70 if self.cgb => self.wram_bank = value & 0b111,
             0x8000..=0x9FFF => self.ppu.write(loc as usize, value),
             0xFE00..=0xFE9F => self.ppu.write(loc as usize, value),
 
-            0xC000..=0xDFFF => self.wram[(loc - 0xC000) as usize] = value,
-            0xE000..=0xFDFF => {
-                let new_loc = loc & (WRAM_SIZE - 1);
-                self.wram[(new_loc) as usize] = value;
-            }
+            0xC000..=0xFDFF => self.wram[self.get_wram_index(loc)] = value,
             0xFFFF => self.interrupt.set_ie(value),
             _ => {} //eprintln!("Write: memory not handled: {:x}", loc),
         }
@@ -148,6 +175,48 @@ impl MemoryBus {
         self.write(at, value);
     }
 
+    fn get_wram_index(&self, loc: u16) -> usize {
+        let offset = (loc & (WRAM_SIZE - 1)) as usize;
+        if offset < WRAM_BANK_SIZE {
+            offset
+        } else {
+            self.wram_bank.max(1) as usize * WRAM_BANK_SIZE + offset - WRAM_BANK_SIZE
+        }
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.cgb
+    }
+
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.cgb || !self.prepare_speed_switch {
+            return false;
+        }
+        self.double_speed = !self.double_speed;
+        self.prepare_speed_switch = false;
+        self.timer.write_div();
+        true
+    }
+
+    fn handle_hdma(&mut self, value: u8) {
+        let blocks = self.hdma.write_control(value);
+        for _ in 0..blocks {
+            self.copy_hdma_block();
+            for _ in 0..8 {
+                self.tick();
+            }
+        }
+    }
+
+    fn copy_hdma_block(&mut self) {
+        let (source, destination) = self.hdma.next_block();
+        for offset in 0..BLOCK_SIZE {
+            let value = self.read(source.wrapping_add(offset));
+            self.ppu
+                .write_vram(destination.wrapping_add(offset) as usize, value);
+        }
+    }
+
     fn handle_dma(&mut self, value: u8) {
         let addr = (value as u16) << 8;
         self.dma = true;
@@ -160,15 +229,26 @@ impl MemoryBus {
         if self.timer.handle_timer() {
             self.interrupt.require_timer();
         }
-        self.ppu.step();
+        if self.serial.tick() {
+            self.interrupt.require_serial();
+        }
+        let dots = if self.double_speed { 2 } else { 4 };
+        self.apu.tick(dots, self.timer.div, self.double_speed);
+        self.ppu.step(dots);
+        if self.ppu.hblank {
+            self.ppu.hblank = false;
+            if self.hdma.is_hblank_active() {
+                self.copy_hdma_block();
+                self.hdma.finish_hblank_block();
+            }
+        }
         if self.ppu.vblank {
             self.interrupt.require_vblank();
             self.ppu.vblank = false;
         }
-        if self.ppu.stat_int || self.ppu.stat_int_ly {
+        if self.ppu.stat_int {
             self.interrupt.require_stat();
             self.ppu.stat_int = false;
-            self.ppu.stat_int_ly = false;
         }
 
         if self.dma {
@@ -182,6 +262,14 @@ impl MemoryBus {
         }
     }
 
+    pub fn rom_checksum(&self) -> u32 {
+        self.cartridge.checksum()
+    }
+
+    pub fn is_bootstrap(&self) -> bool {
+        self.cartridge.bootstrap
+    }
+
     pub fn fetch_next_word(&mut self) -> u16 {
         let low = self.fetch_next_byte();
         let high = self.fetch_next_byte();
@@ -194,3 +282,51 @@ impl MemoryBus {
         self.write_byte(at.wrapping_add(1), high);
     }
 }
+
+impl Saveable for MemoryBus {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.pc);
+        state.write_u128(self.cycle);
+        state.write_bytes(&self.wram);
+        state.write_bytes(&self.hram);
+        state.write_bool(self.cgb);
+        state.write_bool(self.double_speed);
+        state.write_bool(self.prepare_speed_switch);
+        state.write_u8(self.wram_bank);
+        self.hdma.write_state(state);
+        state.write_bool(self.dma);
+        state.write_u16(self.dma_addr);
+        state.write_u16(self.dma_target);
+        self.interrupt.write_state(state);
+        self.timer.write_state(state);
+        self.joypad.write_state(state);
+        self.serial.write_state(state);
+        self.apu.write_state(state);
+        self.ppu.write_state(state);
+        self.cartridge.write_state(state);
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pc = state.read_u16()?;
+        self.cycle = state.read_u128()?;
+        state.read_bytes(&mut self.wram)?;
+        state.read_bytes(&mut self.hram)?;
+        if state.read_bool()? != self.cgb {
+            return Err(StateError::InvalidValue("hardware model"));
+        }
+        self.double_speed = state.read_bool()?;
+        self.prepare_speed_switch = state.read_bool()?;
+        self.wram_bank = state.read_u8()? & 0b111;
+        self.hdma.read_state(state)?;
+        self.dma = state.read_bool()?;
+        self.dma_addr = state.read_u16()?;
+        self.dma_target = state.read_u16()?;
+        self.interrupt.read_state(state)?;
+        self.timer.read_state(state)?;
+        self.joypad.read_state(state)?;
+        self.serial.read_state(state)?;
+        self.apu.read_state(state)?;
+        self.ppu.read_state(state)?;
+        self.cartridge.read_state(state)
+    }
+}
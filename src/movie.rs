@@ -0,0 +1,248 @@
+use crate::emulator::{Emulator, Model};
+use crate::input_script::InputScript;
+use crate::joypad::Buttons;
+
+pub const EMULATOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+const MOVIE_MAGIC: &str = "# game-boy movie";
+
+// The buttons of each frame from power-on, with what is needed to replay them:
+// the rom, the boot rom and the model, and the frame hash reached at the end.
+pub struct Movie {
+    pub emulator_version: String,
+    pub rom_checksum: u32,
+    pub boot_rom_checksum: Option<u32>,
+    pub model: Model,
+    pub frames: u64,
+    pub frame_hash: Option<u64>,
+    input: InputScript,
+}
+
+impl Movie {
+    pub fn new(emulator: &Emulator) -> Movie {
+        Movie {
+            emulator_version: String::from(EMULATOR_VERSION),
+            rom_checksum: emulator.rom_checksum(),
+            boot_rom_checksum: emulator.boot_rom_checksum(),
+            model: emulator.model(),
+            frames: 0,
+            frame_hash: None,
+            input: InputScript::default(),
+        }
+    }
+
+    // The buttons given to the emulator before running the frame.
+    pub fn record(&mut self, frame: u64, buttons: Buttons) {
+        self.input.push(frame, buttons);
+        self.frames = frame;
+    }
+
+    pub fn finish(&mut self, emulator: &Emulator) {
+        self.frames = emulator.frame();
+        self.frame_hash = Some(emulator.frame_hash());
+    }
+
+    pub fn buttons_at(&self, frame: u64) -> Buttons {
+        self.input.buttons_at(frame)
+    }
+
+    pub fn is_finished(&self, frame: u64) -> bool {
+        frame >= self.frames
+    }
+
+    // Refuses an emulator that would not replay the movie the same way.
+    pub fn check(&self, emulator: &Emulator) -> Result<(), String> {
+        if self.rom_checksum != emulator.rom_checksum() {
+            return Err(String::from("the movie was recorded with another rom"));
+        }
+        if self.boot_rom_checksum != emulator.boot_rom_checksum() {
+            return Err(String::from("the movie was recorded with another boot rom"));
+        }
+        if self.model != emulator.model() {
+            return Err(format!(
+                "the movie was recorded on the {} model",
+                get_model_name(self.model)
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn verify(&self, emulator: &Emulator) -> Result<(), String> {
+        let Some(expected) = self.frame_hash else {
+            return Err(String::from("the movie has no frame hash"));
+        };
+        if emulator.frame() != self.frames {
+            return Err(format!(
+                "stopped at frame {}, the movie has {} frames",
+                emulator.frame(),
+                self.frames
+            ));
+        }
+        if emulator.frame_hash() != expected {
+            return Err(format!(
+                "frame hash {:016x} does not match {:016x}",
+                emulator.frame_hash(),
+                expected
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        if !text.starts_with(MOVIE_MAGIC) {
+            return Err(String::from("not a movie file"));
+        }
+        let mut movie = Movie {
+            emulator_version: String::new(),
+            rom_checksum: 0,
+            boot_rom_checksum: None,
+            model: Model::Dmg,
+            frames: 0,
+            frame_hash: None,
+            input: InputScript::default(),
+        };
+        let mut keys = vec![];
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let res = if line.starts_with(|c: char| c.is_ascii_digit()) {
+                movie.input.parse_line(line)
+            } else if let Some((key, value)) = line.split_once(' ') {
+                keys.push(key.to_string());
+                movie.set_header(key, value.trim())
+            } else {
+                Err(format!("expected a value for {}", line))
+            };
+            res.map_err(|err| format!("line {}: {}", index + 1, err))?;
+        }
+        for key in ["emulator", "rom", "model", "frames"] {
+            if !keys.iter().any(|found| found == key) {
+                return Err(format!("missing {} in the movie header", key));
+            }
+        }
+        Ok(movie)
+    }
+
+    fn set_header(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("invalid {} {}", key, value);
+        match key {
+            "emulator" => self.emulator_version = value.to_string(),
+            "rom" => self.rom_checksum = u32::from_str_radix(value, 16).map_err(|_| invalid())?,
+            "boot_rom" if value == "none" => self.boot_rom_checksum = None,
+            "boot_rom" => {
+                self.boot_rom_checksum =
+                    Some(u32::from_str_radix(value, 16).map_err(|_| invalid())?)
+            }
+            "model" => {
+                self.model = match value {
+                    "dmg" => Model::Dmg,
+                    "cgb" => Model::Cgb,
+                    _ => return Err(invalid()),
+                }
+            }
+            "frames" => self.frames = value.parse().map_err(|_| invalid())?,
+            "hash" => {
+                self.frame_hash = Some(u64::from_str_radix(value, 16).map_err(|_| invalid())?)
+            }
+            _ => return Err(format!("unknown movie header {}", key)),
+        }
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", MOVIE_MAGIC);
+        text += &format!("emulator {}\n", self.emulator_version);
+        text += &format!("rom {:08x}\n", self.rom_checksum);
+        text += &match self.boot_rom_checksum {
+            Some(checksum) => format!("boot_rom {:08x}\n", checksum),
+            None => String::from("boot_rom none\n"),
+        };
+        text += &format!("model {}\n", get_model_name(self.model));
+        text += &format!("frames {}\n", self.frames);
+        if let Some(hash) = self.frame_hash {
+            text += &format!("hash {:016x}\n", hash);
+        }
+        text + &self.input.to_text()
+    }
+}
+
+fn get_model_name(model: Model) -> &'static str {
+    match model {
+        Model::Dmg => "dmg",
+        Model::Cgb => "cgb",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::EmulatorOptions;
+
+    // Selects the directions and copies P1 to the background palette, so the
+    // frame shows the pressed directions.
+    fn get_rom() -> Vec<u8> {
+        let program = [0x3E, 0x20, 0xE0, 0x00, 0xF0, 0x00, 0xE0, 0x47, 0x18, 0xFA];
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+        rom
+    }
+
+    fn record_movie(rom: &[u8]) -> Movie {
+        let mut emulator = Emulator::new(rom, EmulatorOptions::default());
+        let mut movie = Movie::new(&emulator);
+        while emulator.frame() < 20 {
+            let buttons = Buttons {
+                right: (5..12).contains(&emulator.frame()),
+                left: emulator.frame() >= 15,
+                ..Buttons::default()
+            };
+            movie.record(emulator.frame(), buttons);
+            emulator.set_buttons(buttons);
+            emulator.run_frame();
+        }
+        movie.finish(&emulator);
+        movie
+    }
+
+    #[test]
+    fn it_should_replay_to_the_same_frame_hash() {
+        let rom = get_rom();
+        let movie = Movie::parse(&record_movie(&rom).to_text()).unwrap();
+        let mut emulator = Emulator::new(&rom, EmulatorOptions::default());
+
+        movie.check(&emulator).unwrap();
+        while !movie.is_finished(emulator.frame()) {
+            emulator.set_buttons(movie.buttons_at(emulator.frame()));
+            emulator.run_frame();
+        }
+
+        assert_eq!(movie.frames, 20);
+        assert_eq!(movie.verify(&emulator), Ok(()));
+    }
+
+    #[test]
+    fn it_should_detect_a_desync() {
+        let rom = get_rom();
+        let movie = record_movie(&rom);
+        let mut emulator = Emulator::new(&rom, EmulatorOptions::default());
+
+        while !movie.is_finished(emulator.frame()) {
+            emulator.run_frame();
+        }
+
+        assert!(movie.verify(&emulator).is_err());
+    }
+
+    #[test]
+    fn it_should_refuse_another_rom() {
+        let movie = record_movie(&get_rom());
+        let mut rom = get_rom();
+        rom[0x200] = 1;
+        let emulator = Emulator::new(&rom, EmulatorOptions::default());
+
+        assert!(movie.check(&emulator).is_err());
+        assert!(Movie::parse("rom 1234\n").is_err());
+        assert!(Movie::parse("# game-boy movie\nemulator 0.1.0\n").is_err());
+    }
+}
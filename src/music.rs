@@ -0,0 +1,142 @@
+use crate::{
+    cli::Options,
+    gameboy::{handle_exit, open_synced_audio},
+    keyboard::{Action, KeyBindings},
+    recorder::{record_audio, toggle_recording, Recorder},
+    sound_debug::{handle_channel_keys, AudioWindow},
+};
+use game_boy::{
+    apu::DEFAULT_SAMPLE_RATE,
+    gbs::GbsPlayer,
+    pacing::FramePacer,
+    ppu::config::{HEIGHT, WIDTH},
+};
+use minifb::Window;
+use std::fs;
+
+pub fn is_music_file(filename: &str) -> bool {
+    filename.to_lowercase().ends_with(".gbs")
+}
+
+pub fn load_player(options: &Options) -> Result<GbsPlayer, String> {
+    let data =
+        fs::read(&options.rom).map_err(|err| format!("cannot read {}: {}", options.rom, err))?;
+    let mut player = GbsPlayer::new(&data, DEFAULT_SAMPLE_RATE)
+        .map_err(|err| format!("{}: {}", options.rom, err))?;
+    if let Some(filter) = options.audio_filter {
+        player.set_filter(filter);
+    }
+    if let Some(track) = options.track {
+        if track > player.header().song_count {
+            return Err(format!(
+                "{} only has {} songs",
+                options.rom,
+                player.header().song_count
+            ));
+        }
+        player.play_song(track - 1);
+    }
+    let header = player.header();
+    println!("{}", header.title);
+    println!("{}", header.author);
+    println!("{}", header.copyright);
+    Ok(player)
+}
+
+pub fn run_music_headless(mut player: GbsPlayer, options: &Options) -> Result<(), String> {
+    let mut recorder = match &options.wav {
+        Some(filename) => Some(Recorder::start(
+            filename,
+            player.sample_rate(),
+            options.wav_channels,
+        )?),
+        None => None,
+    };
+    player.set_record_channels(options.wav_channels);
+    let mut frame = 0;
+    while !is_headless_done(&player, frame, options) {
+        player.run_frame();
+        frame += 1;
+        let samples = player.audio_samples();
+        if let Some(recorder) = &mut recorder {
+            recorder.record(&samples, &player.channel_samples())?;
+        }
+    }
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+    Ok(())
+}
+
+fn is_headless_done(player: &GbsPlayer, frame: u64, options: &Options) -> bool {
+    options.frames.is_some_and(|limit| frame >= limit)
+        || options
+            .cycles
+            .is_some_and(|limit| player.cycles() >= limit as u128)
+}
+
+pub fn run_music(
+    mut player: GbsPlayer,
+    options: &Options,
+    keys: &KeyBindings,
+    window: &mut Window,
+    audio_window: &mut Option<AudioWindow>,
+) {
+    let mut pacer = FramePacer::new();
+    let (mut audio, synced) = open_synced_audio(options.sync);
+    let mut recorder = None;
+    if options.wav.is_some() {
+        toggle_recording(player.sample_rate(), options, &mut recorder);
+    }
+    player.set_record_channels(options.wav_channels || audio_window.is_some());
+    let screen = vec![0; WIDTH * HEIGHT];
+    let mut frame = 0;
+    update_title(window, &player);
+    loop {
+        player.run_frame();
+        frame += 1;
+        let samples = player.audio_samples();
+        let channel_samples = player.channel_samples();
+        audio.push(&samples, player.sample_rate());
+        record_audio(&samples, &channel_samples, &mut recorder);
+        window.update_with_buffer(&screen, WIDTH, HEIGHT).unwrap();
+        if let Some(audio_window) = audio_window {
+            audio_window.push(&channel_samples);
+            audio_window.render(player.apu());
+        }
+        handle_channel_keys(window, keys, player.apu_mut());
+        if keys.is_pressed(window, Action::NextSong) {
+            player.next_song();
+            update_title(window, &player);
+        }
+        if keys.is_pressed(window, Action::PreviousSong) {
+            player.previous_song();
+            update_title(window, &player);
+        }
+        if keys.is_pressed(window, Action::Record) {
+            toggle_recording(player.sample_rate(), options, &mut recorder);
+        }
+        if synced {
+            pacer.wait_for_audio(audio.sink());
+        } else {
+            pacer.wait();
+        }
+        if options.frames.is_some_and(|limit| frame >= limit) || handle_exit(window, keys) {
+            if recorder.is_some() {
+                toggle_recording(player.sample_rate(), options, &mut recorder);
+            }
+            return;
+        }
+    }
+}
+
+fn update_title(window: &mut Window, player: &GbsPlayer) {
+    let title = format!(
+        "{} - song {}/{}",
+        player.header().title,
+        player.song() + 1,
+        player.header().song_count
+    );
+    println!("{}", title);
+    window.set_title(&title);
+}
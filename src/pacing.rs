@@ -0,0 +1,272 @@
+#![allow(clippy::new_without_default)]
+
+use crate::audio::AudioSink;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const CPU_FREQUENCY: u32 = 4_194_304;
+pub const DOTS_PER_FRAME: u32 = 70_224;
+pub const FRAME_RATE: f64 = CPU_FREQUENCY as f64 / DOTS_PER_FRAME as f64;
+
+const MAX_LATE_FRAMES: u32 = 4;
+const AUDIO_POLL: Duration = Duration::from_millis(1);
+
+// What the frames follow: the wall clock, or the sound device which plays the
+// samples at its own pace.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SyncMode {
+    Video,
+    Audio,
+}
+
+// Where the pacer reads the time and waits, the tests move a fake one.
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&mut self, duration: Duration);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+pub struct FramePacer<C: Clock = SystemClock> {
+    clock: C,
+    base_period: Duration,
+    period: Duration,
+    uncapped: bool,
+    deadline: Instant,
+    last_present: Instant,
+    measure_start: Instant,
+    measured_frames: u32,
+    fps: f64,
+}
+
+impl FramePacer {
+    pub fn new() -> FramePacer {
+        FramePacer::with_clock(SystemClock)
+    }
+}
+
+impl<C: Clock> FramePacer<C> {
+    pub fn with_clock(clock: C) -> FramePacer<C> {
+        let now = clock.now();
+        let period = Duration::from_secs_f64(1.0 / FRAME_RATE);
+        FramePacer {
+            clock,
+            base_period: period,
+            period,
+            uncapped: false,
+            deadline: now + period,
+            last_present: now,
+            measure_start: now,
+            measured_frames: 0,
+            fps: 0.0,
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: Option<f64>) {
+        match speed {
+            Some(speed) if speed > 0.0 => {
+                let period = self.base_period.div_f64(speed);
+                if self.uncapped || period != self.period {
+                    self.deadline = self.clock.now() + period;
+                }
+                self.uncapped = false;
+                self.period = period;
+            }
+            _ => self.uncapped = true,
+        }
+    }
+
+    pub fn should_present(&mut self) -> bool {
+        let now = self.clock.now();
+        if now - self.last_present < self.base_period {
+            return false;
+        }
+        self.last_present = now;
+        true
+    }
+
+    pub fn wait(&mut self) -> bool {
+        let now = self.clock.now();
+        if self.uncapped {
+            self.deadline = now;
+        } else if now < self.deadline {
+            self.clock.sleep(self.deadline - now);
+        } else if now - self.deadline > self.period * MAX_LATE_FRAMES {
+            self.deadline = now;
+        }
+        self.deadline += self.period;
+        self.measure()
+    }
+
+    // Waits until the sink has played down to half its capacity. A sink that
+    // stops draining is given up on after a few frames.
+    pub fn wait_for_audio(&mut self, sink: &dyn AudioSink) -> bool {
+        let start = self.clock.now();
+        let target = sink.capacity() / 2;
+        while sink.queued() > target && self.clock.now() - start < self.period * MAX_LATE_FRAMES {
+            self.clock.sleep(AUDIO_POLL);
+        }
+        self.deadline = self.clock.now() + self.period;
+        self.measure()
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.fps
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.fps / FRAME_RATE * 100.0
+    }
+
+    fn measure(&mut self) -> bool {
+        self.measured_frames += 1;
+        let now = self.clock.now();
+        let elapsed = now - self.measure_start;
+        if elapsed < Duration::from_secs(1) {
+            return false;
+        }
+        self.fps = self.measured_frames as f64 / elapsed.as_secs_f64();
+        self.measured_frames = 0;
+        self.measure_start = now;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_run_at_gameboy_frame_rate() {
+        assert!((FRAME_RATE - 59.7275).abs() < 0.0001);
+    }
+
+    // Only moves when the pacer sleeps, or when a test spends time.
+    struct FakeClock {
+        now: Instant,
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.now
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.now += duration;
+        }
+    }
+
+    fn get_pacer() -> (FramePacer<FakeClock>, Instant) {
+        let start = Instant::now();
+        (FramePacer::with_clock(FakeClock { now: start }), start)
+    }
+
+    fn get_periods(pacer: &FramePacer<FakeClock>, start: Instant) -> f64 {
+        (pacer.clock.now - start).as_secs_f64() * FRAME_RATE
+    }
+
+    #[test]
+    fn it_should_not_sleep_when_uncapped() {
+        let (mut pacer, start) = get_pacer();
+        pacer.set_speed(None);
+        for _ in 0..100 {
+            pacer.wait();
+        }
+
+        assert_eq!(pacer.clock.now, start);
+    }
+
+    #[test]
+    fn it_should_slow_down() {
+        let (mut pacer, start) = get_pacer();
+        pacer.set_speed(Some(0.5));
+        for _ in 0..3 {
+            pacer.wait();
+        }
+
+        assert!((get_periods(&pacer, start) - 6.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn it_should_not_drift() {
+        let (mut pacer, start) = get_pacer();
+        for frame in 0..6 {
+            // Some frames take longer to emulate than others.
+            pacer.clock.now += Duration::from_millis(frame % 3 * 5);
+            pacer.wait();
+        }
+
+        assert!((get_periods(&pacer, start) - 6.0).abs() < 0.001);
+    }
+
+    // Plays one frame each time it is asked for its fill.
+    struct DrainingSink {
+        queued: std::cell::Cell<usize>,
+    }
+
+    impl AudioSink for DrainingSink {
+        fn sample_rate(&self) -> u32 {
+            44_100
+        }
+
+        fn queued(&self) -> usize {
+            let queued = self.queued.get();
+            self.queued.set(queued.saturating_sub(1));
+            queued
+        }
+
+        fn capacity(&self) -> usize {
+            100
+        }
+
+        fn push(&mut self, _samples: &[f32]) {}
+    }
+
+    #[test]
+    fn it_should_wait_for_the_audio_queue() {
+        let (mut pacer, start) = get_pacer();
+        let sink = DrainingSink {
+            queued: std::cell::Cell::new(60),
+        };
+
+        pacer.wait_for_audio(&sink);
+        assert_eq!(pacer.clock.now - start, AUDIO_POLL * 10);
+        pacer.wait_for_audio(&sink);
+        assert_eq!(pacer.clock.now - start, AUDIO_POLL * 10);
+    }
+
+    #[test]
+    fn it_should_give_up_on_a_stuck_audio_queue() {
+        let (mut pacer, start) = get_pacer();
+        let sink = DrainingSink {
+            queued: std::cell::Cell::new(usize::MAX),
+        };
+
+        pacer.wait_for_audio(&sink);
+
+        let frames = get_periods(&pacer, start);
+        assert!((MAX_LATE_FRAMES as f64..MAX_LATE_FRAMES as f64 + 0.1).contains(&frames));
+    }
+
+    #[test]
+    fn it_should_catch_up_late_frames() {
+        let (mut pacer, start) = get_pacer();
+        pacer.clock.now += Duration::from_secs(1);
+        for _ in 0..3 {
+            pacer.wait();
+        }
+
+        let late = (pacer.clock.now - start).as_secs_f64() - 1.0;
+        assert!((late * FRAME_RATE - 2.0).abs() < 0.001);
+    }
+}
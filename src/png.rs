@@ -0,0 +1,116 @@
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+pub fn encode_png(pixels: &[u32], width: usize, height: usize) -> Vec<u8> {
+    let mut retval = SIGNATURE.to_vec();
+    let mut header = vec![];
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(&mut retval, b"IHDR", &header);
+    write_chunk(
+        &mut retval,
+        b"IDAT",
+        &zlib_stored(&get_scanlines(pixels, width)),
+    );
+    write_chunk(&mut retval, b"IEND", &[]);
+    retval
+}
+
+fn get_scanlines(pixels: &[u32], width: usize) -> Vec<u8> {
+    let mut retval = vec![];
+    for row in pixels.chunks(width) {
+        retval.push(0);
+        for pixel in row {
+            retval.push((pixel >> 16) as u8);
+            retval.push((pixel >> 8) as u8);
+            retval.push(*pixel as u8);
+        }
+    }
+    retval
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut retval = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        retval.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        retval.push(last as u8);
+        retval.extend_from_slice(&len.to_le_bytes());
+        retval.extend_from_slice(&(!len).to_le_bytes());
+        retval.extend_from_slice(block);
+    }
+    retval.extend_from_slice(&adler32(data).to_be_bytes());
+    retval
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_compute_crc32() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+    }
+
+    #[test]
+    fn it_should_compute_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn it_should_encode_header() {
+        let png = encode_png(&[0xFF0000, 0x00FF00], 2, 1);
+
+        assert_eq!(&png[..8], &SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..20], &2u32.to_be_bytes());
+        assert_eq!(&png[20..24], &1u32.to_be_bytes());
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    }
+
+    #[test]
+    fn it_should_split_large_images_in_blocks() {
+        let data = vec![7; MAX_STORED_BLOCK + 10];
+
+        let zlib = zlib_stored(&data);
+
+        assert_eq!(zlib.len(), 2 + 5 + MAX_STORED_BLOCK + 5 + 10 + 4);
+        assert_eq!(zlib[2], 0);
+        assert_eq!(zlib[2 + 5 + MAX_STORED_BLOCK], 1);
+    }
+}
@@ -1,42 +1,67 @@
 #![allow(clippy::new_without_default)]
 
 pub mod color;
+pub mod compat;
 pub mod config;
+pub mod fetcher;
 pub mod lcdc;
 pub mod object;
 pub mod renderer;
+pub mod savestate;
 pub mod stat;
 pub mod state_handler;
 pub mod vram;
 
 use crate::ppu::object::Object;
-use color::from_u8_rgb;
+use color::{from_u8_rgb, DmgPalettes, Palette, GREY_PALETTE};
 use config::State;
-use config::{Tile, DEBUG_BUFFER, DEBUG_HEIGHT, DEBUG_WIDTH, OAM_SIZE, VIDEO_BUFFER, VRAM_SIZE};
+use config::{
+    Tile, DEBUG_BUFFER, DEBUG_HEIGHT, DEBUG_WIDTH, OAM_SIZE, PALETTE_RAM_SIZE, TILES_PER_BANK,
+    VIDEO_BUFFER, VRAM_SIZE,
+};
+use fetcher::{BgPixel, Fetcher, ObjectPixel};
+use std::collections::VecDeque;
 
 use crate::ppu::color::get_u32_color;
 use std::fmt;
 
 pub struct Ppu {
     pub vblank: bool,
+    pub hblank: bool,
     pub stat_int: bool,
-    pub stat_int_ly: bool,
     pub dot: u32,
-    pub video_buffer: [u32; VIDEO_BUFFER],
-    pub bg_trace: [u32; VIDEO_BUFFER],
+    pub video_buffer: Vec<u32>,
+    // The DMG shades with their palette in bits 2-3 (bg, obj0, obj1), the frame
+    // hash reads them so it does not depend on the host colors.
+    pub shade_buffer: Vec<u8>,
     pub objects: Vec<Object>,
     pub frame_drawn: bool,
 
+    palettes: DmgPalettes,
+    cgb: bool,
     window_ly: u8,
-    scanline_drawn: bool,
+    wy_triggered: bool,
+    window_drawn: bool,
+    window_wrap: bool,
+    stat_line: bool,
     x: u8,
     ly: u8,
     lyc: u8,
     state: State,
 
-    debug_tiles: [u32; DEBUG_BUFFER],
-    vram: [u8; VRAM_SIZE],
-    tiles: [Tile; 384],
+    fetcher: Fetcher,
+    bg_fifo: VecDeque<BgPixel>,
+    obj_fifo: VecDeque<ObjectPixel>,
+    line_objects: Vec<Object>,
+    pending_object: Option<Object>,
+    object_dots: u8,
+    startup: u8,
+    discard: u8,
+
+    debug_tiles: Vec<u32>,
+    vram: Vec<u8>,
+    vram_bank: u8,
+    tiles: Vec<Tile>,
     oam: [u8; OAM_SIZE],
 
     dma: u8,
@@ -51,29 +76,49 @@ pub struct Ppu {
     bgp: u8,
     obp0: u8,
     obp1: u8,
+
+    bcps: u8,
+    ocps: u8,
+    bg_palette_ram: [u8; PALETTE_RAM_SIZE],
+    obj_palette_ram: [u8; PALETTE_RAM_SIZE],
 }
 
 impl Ppu {
     pub fn new() -> Ppu {
         Ppu {
             vblank: false,
+            hblank: false,
             stat_int: false,
-            stat_int_ly: false,
-            video_buffer: [0; VIDEO_BUFFER],
-            bg_trace: [0; VIDEO_BUFFER],
+            video_buffer: vec![0; VIDEO_BUFFER],
+            shade_buffer: vec![0; VIDEO_BUFFER],
             dot: 0,
             frame_drawn: false,
 
+            palettes: DmgPalettes::uniform(GREY_PALETTE),
+            cgb: false,
             window_ly: 0,
-            scanline_drawn: false,
+            wy_triggered: false,
+            window_drawn: false,
+            window_wrap: false,
+            stat_line: false,
             state: State::Mode2,
             ly: 0,
             lyc: 0,
             x: 0,
 
-            debug_tiles: [0; DEBUG_BUFFER],
-            vram: [0; VRAM_SIZE],
-            tiles: [[[0; 8]; 8]; 384],
+            fetcher: Fetcher::new(),
+            bg_fifo: VecDeque::with_capacity(16),
+            obj_fifo: VecDeque::with_capacity(8),
+            line_objects: vec![],
+            pending_object: None,
+            object_dots: 0,
+            startup: 0,
+            discard: 0,
+
+            debug_tiles: vec![0; DEBUG_BUFFER],
+            vram: vec![0; VRAM_SIZE],
+            vram_bank: 0,
+            tiles: vec![[[0; 8]; 8]; 2 * TILES_PER_BANK],
             oam: [0; OAM_SIZE],
             objects: vec![],
 
@@ -89,28 +134,52 @@ impl Ppu {
             bgp: 0,
             obp0: 0,
             obp1: 0,
+
+            bcps: 0,
+            ocps: 0,
+            bg_palette_ram: [0xFF; PALETTE_RAM_SIZE],
+            obj_palette_ram: [0xFF; PALETTE_RAM_SIZE],
         }
     }
 
-    pub fn step(&mut self) {
+    pub fn step(&mut self, dots: u32) {
         if !self.is_lcd_active() {
             return;
         }
-        for _ in 0..=4 {
+        for _ in 0..dots {
             self.run_ppu();
         }
     }
 
+    pub fn set_cgb(&mut self, cgb: bool) {
+        self.cgb = cgb;
+    }
+
     pub fn get_video_buffer(&mut self) -> &[u32] {
         &self.video_buffer
     }
 
+    // The CGB colors come from the palette RAM written by the game.
+    pub fn frame_hash(&self) -> u64 {
+        let bytes: Vec<u8> = match self.cgb {
+            true => self
+                .video_buffer
+                .iter()
+                .flat_map(|pixel| pixel.to_le_bytes())
+                .collect(),
+            false => self.shade_buffer.clone(),
+        };
+        bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01B3)
+        })
+    }
+
     pub fn get_tiles_memory(&mut self) -> &[u32] {
         let mut y: usize = 0;
         let mut x: usize = 0;
         self.cover_with_red();
-        for tile in self.tiles.iter() {
-            write_tile_in_debug_buffer(tile, &mut self.debug_tiles, x, y);
+        for tile in self.tiles.iter().take(TILES_PER_BANK) {
+            write_tile_in_debug_buffer(tile, &mut self.debug_tiles, &self.palettes.bg, x, y);
             x += 9;
             if x >= DEBUG_WIDTH {
                 x = 0;
@@ -132,10 +201,16 @@ impl Ppu {
     }
 }
 
-fn write_tile_in_debug_buffer(tile: &Tile, buffer: &mut [u32], x: usize, y: usize) {
+fn write_tile_in_debug_buffer(
+    tile: &Tile,
+    buffer: &mut [u32],
+    palette: &Palette,
+    x: usize,
+    y: usize,
+) {
     for yd in 0..8 {
         for xd in 0..8 {
-            buffer[(y + yd) * DEBUG_WIDTH + xd + x] = get_u32_color(tile[yd][xd]);
+            buffer[(y + yd) * DEBUG_WIDTH + xd + x] = get_u32_color(palette, tile[yd][xd]);
         }
     }
 }
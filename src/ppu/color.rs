@@ -1,5 +1,40 @@
+use crate::ppu::config::PALETTE_RAM_SIZE;
+use crate::ppu::fetcher::{BgPixel, ObjectPixel};
 use crate::ppu::Ppu;
 
+pub type Palette = [u32; 4];
+
+pub const GREY_PALETTE: Palette = [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000];
+pub const GREEN_PALETTE: Palette = [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F];
+pub const POCKET_PALETTE: Palette = [0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F];
+pub const LIGHT_PALETTE: Palette = [0x00B581, 0x009A71, 0x00694A, 0x004F3B];
+pub const CONTRAST_PALETTE: Palette = [0xFFFFFF, 0xBFBFBF, 0x3F3F3F, 0x000000];
+
+pub const PRESETS: [(&str, Palette); 5] = [
+    ("grey", GREY_PALETTE),
+    ("green", GREEN_PALETTE),
+    ("pocket", POCKET_PALETTE),
+    ("light", LIGHT_PALETTE),
+    ("contrast", CONTRAST_PALETTE),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DmgPalettes {
+    pub bg: Palette,
+    pub obj0: Palette,
+    pub obj1: Palette,
+}
+
+impl DmgPalettes {
+    pub fn uniform(palette: Palette) -> DmgPalettes {
+        DmgPalettes {
+            bg: palette,
+            obj0: palette,
+            obj1: palette,
+        }
+    }
+}
+
 impl Ppu {
     pub fn get_color_from_bg_palette(&mut self, value: u8) -> u8 {
         match value & 0b0000_0011 {
@@ -10,32 +45,104 @@ impl Ppu {
             _ => 0,
         }
     }
+    pub fn get_cgb_bg_color(&self, pixel: BgPixel) -> u32 {
+        get_palette_ram_color(&self.bg_palette_ram, pixel.attributes, pixel.color)
+    }
+
+    pub fn get_cgb_obj_color(&self, pixel: ObjectPixel) -> u32 {
+        get_palette_ram_color(&self.obj_palette_ram, pixel.flags, pixel.color)
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palettes = DmgPalettes::uniform(palette);
+    }
+
+    pub fn set_palettes(&mut self, palettes: DmgPalettes) {
+        self.palettes = palettes;
+    }
+
     pub fn get_sprite_color(&mut self, value: u8, flags: u8) -> u8 {
         let mut palette = self.obp0;
         if flags & 0b1_0000 != 0 {
             palette = self.obp1;
         }
         match value & 0b0000_0011 {
-            0 => 0x6F,
+            0 => palette & 0b0000_0011,
             1 => (palette >> 2) & 0b0000_0011,
             2 => (palette >> 4) & 0b0000_0011,
             3 => (palette >> 6) & 0b0000_0011,
-            _ => 0x5F,
+            _ => 0,
         }
     }
 }
 
-pub fn get_u32_color(value: u8) -> u32 {
-    match value {
-        0b11 => from_u8_rgb(0x00, 0x00, 0x00),
-        0b01 => from_u8_rgb(0xAA, 0xAA, 0xAA),
-        0b10 => from_u8_rgb(0x55, 0x55, 0x55),
-        0b00 => from_u8_rgb(0xFF, 0xFF, 0xFF),
-        _ => 0,
+pub fn get_palette(name: &str) -> Option<Palette> {
+    PRESETS
+        .iter()
+        .find(|(preset, _)| *preset == name)
+        .map(|(_, palette)| *palette)
+}
+
+pub fn parse_palette(value: &str) -> Result<Palette, String> {
+    let colors: Vec<&str> = value.split_whitespace().collect();
+    if colors.len() != 4 {
+        return Err(format!("expected 4 colors in palette {}", value));
+    }
+    let mut palette = [0; 4];
+    for (color, text) in palette.iter_mut().zip(colors) {
+        let hex = text.strip_prefix('#').unwrap_or(text);
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("invalid color {}", text));
+        }
+        *color = u32::from_str_radix(hex, 16).map_err(|_| format!("invalid color {}", text))?;
     }
+    Ok(palette)
+}
+
+pub fn get_u32_color(palette: &Palette, value: u8) -> u32 {
+    palette[(value & 0b11) as usize]
+}
+
+fn get_palette_ram_color(ram: &[u8; PALETTE_RAM_SIZE], palette: u8, color: u8) -> u32 {
+    let index = (palette & 0b111) as usize * 8 + (color & 0b11) as usize * 2;
+    from_rgb555(u16::from_le_bytes([ram[index], ram[index + 1]]))
+}
+
+pub fn from_rgb555(value: u16) -> u32 {
+    let scale = |component: u16| {
+        let component = (component & 0x1F) as u8;
+        (component << 3) | (component >> 2)
+    };
+    from_u8_rgb(scale(value), scale(value >> 5), scale(value >> 10))
 }
 
 pub fn from_u8_rgb(r: u8, g: u8, b: u8) -> u32 {
     let (r, g, b) = (r as u32, g as u32, b as u32);
     (r << 16) | (g << 8) | b
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_parse_palette() {
+        let palette = parse_palette("#E0F8D0 88C070 346856 081820").unwrap();
+
+        assert_eq!(palette, [0xE0F8D0, 0x88C070, 0x346856, 0x081820]);
+    }
+
+    #[test]
+    fn it_should_refuse_invalid_palette() {
+        assert!(parse_palette("FFFFFF 000000").is_err());
+        assert!(parse_palette("FFFFFF AAAAAA 555555 00000G").is_err());
+        assert!(parse_palette("FFFFFF AAAAAA 555555 0000").is_err());
+    }
+
+    #[test]
+    fn it_should_convert_rgb555() {
+        assert_eq!(from_rgb555(0x7FFF), 0xFFFFFF);
+        assert_eq!(from_rgb555(0x001F), 0xFF0000);
+        assert_eq!(from_rgb555(0x0200), 0x008400);
+    }
+}
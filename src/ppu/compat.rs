@@ -0,0 +1,222 @@
+use crate::cartridge::header::Header;
+use crate::joypad::Buttons;
+use crate::ppu::color::{from_rgb555, DmgPalettes, Palette};
+
+const COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000, //
+    0x639F, 0x4279, 0x15B0, 0x04CB, //
+    0x7FFF, 0x6E31, 0x454A, 0x0000, //
+    0x7FFF, 0x1BEF, 0x0200, 0x0000, //
+    0x7FFF, 0x421F, 0x1CF2, 0x0000, //
+    0x7FFF, 0x5294, 0x294A, 0x0000, //
+    0x7FFF, 0x03FF, 0x012F, 0x0000, //
+    0x7FFF, 0x03EF, 0x01D6, 0x0000, //
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000, //
+    0x7E74, 0x03FF, 0x0180, 0x0000, //
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B, //
+    0x7ED6, 0x4BFF, 0x2175, 0x0000, //
+    0x53FF, 0x4A5F, 0x7E52, 0x0000, //
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0, //
+    0x03ED, 0x7FFF, 0x255F, 0x0000, //
+    0x036A, 0x021F, 0x03FF, 0x7FFF, //
+    0x7FFF, 0x01DF, 0x0112, 0x0000, //
+    0x231F, 0x035F, 0x00F2, 0x0009, //
+    0x7FFF, 0x03EA, 0x011F, 0x0000, //
+    0x299F, 0x001A, 0x000C, 0x0000, //
+    0x7FFF, 0x027F, 0x001F, 0x0000, //
+    0x7FFF, 0x03E0, 0x0206, 0x0120, //
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00, //
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F, //
+    0x7FFF, 0x03FF, 0x001F, 0x0000, //
+    0x03FF, 0x001F, 0x000C, 0x0000, //
+    0x7FFF, 0x033F, 0x0193, 0x0000, //
+    0x0000, 0x4200, 0x037F, 0x7FFF, //
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000, //
+    0x7FFF, 0x1BEF, 0x6180, 0x0000, //
+];
+
+// Offsets in COLORS of the OBJ0, OBJ1 and BG palettes. A few combinations
+// start in the middle of a palette, like the boot rom does.
+const COMBINATIONS: [[usize; 3]; 51] = [
+    [16, 16, 116],
+    [72, 72, 72],
+    [80, 80, 80],
+    [96, 96, 96],
+    [36, 36, 36],
+    [0, 0, 0],
+    [108, 108, 108],
+    [20, 20, 20],
+    [48, 48, 48],
+    [104, 104, 104],
+    [64, 32, 32],
+    [16, 112, 112],
+    [16, 8, 8],
+    [12, 16, 16],
+    [16, 116, 116],
+    [112, 16, 112],
+    [8, 68, 8],
+    [64, 64, 32],
+    [16, 16, 28],
+    [16, 16, 72],
+    [16, 16, 80],
+    [76, 76, 36],
+    [15, 15, 44],
+    [68, 68, 8],
+    [16, 16, 8],
+    [16, 16, 12],
+    [112, 112, 0],
+    [12, 12, 0],
+    [0, 0, 4],
+    [72, 88, 72],
+    [80, 88, 80],
+    [96, 88, 96],
+    [64, 88, 32],
+    [68, 16, 52],
+    [111, 0, 56],
+    [111, 16, 60],
+    [76, 91, 36],
+    [64, 112, 40],
+    [16, 92, 112],
+    [68, 88, 8],
+    [16, 0, 8],
+    [16, 112, 12],
+    [112, 12, 0],
+    [12, 112, 16],
+    [84, 112, 16],
+    [12, 112, 0],
+    [100, 12, 112],
+    [0, 112, 32],
+    [16, 12, 112],
+    [112, 12, 24],
+    [16, 112, 116],
+];
+
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+    0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+];
+
+// Checksums from this index on are shared by several games, the fourth letter
+// of the title tells them apart.
+const FIRST_DUPLICATE: usize = 65;
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+const CHECKSUM_COMBINATIONS: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5, 29,
+    5, 18, 9, 3, 2, 26, 25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34, 5, 42, 6,
+    5, 33, 25, 42, 42, 40, 14, 16, 25, 42, 42, 5, 0, 39, 36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39,
+    24, 31, 50, 17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+pub fn get_compat_palettes(header: &Header, buttons: Buttons) -> DmgPalettes {
+    let index = get_manual_combination(buttons)
+        .unwrap_or_else(|| CHECKSUM_COMBINATIONS[get_checksum_index(header)] as usize);
+    let [obj0, obj1, bg] = COMBINATIONS[index];
+    DmgPalettes {
+        bg: get_palette(bg),
+        obj0: get_palette(obj0),
+        obj1: get_palette(obj1),
+    }
+}
+
+fn get_checksum_index(header: &Header) -> usize {
+    if !header.is_nintendo() {
+        return 0;
+    }
+    let checksum = header.title_checksum();
+    let letter = header.title_letter(3);
+    TITLE_CHECKSUMS
+        .iter()
+        .enumerate()
+        .position(|(index, value)| {
+            *value == checksum
+                && (index < FIRST_DUPLICATE || FOURTH_LETTERS[index - FIRST_DUPLICATE] == letter)
+        })
+        .unwrap_or(0)
+}
+
+fn get_manual_combination(buttons: Buttons) -> Option<usize> {
+    let direction = match (buttons.right, buttons.left, buttons.up, buttons.down) {
+        (true, false, false, false) => 0,
+        (false, true, false, false) => 1,
+        (false, false, true, false) => 2,
+        (false, false, false, true) => 3,
+        _ => return None,
+    };
+    let combinations = match (buttons.a, buttons.b) {
+        (false, false) => [1, 48, 5, 8],
+        (true, false) => [0, 40, 43, 3],
+        (false, true) => [6, 7, 28, 49],
+        (true, true) => return None,
+    };
+    Some(combinations[direction])
+}
+
+fn get_palette(offset: usize) -> Palette {
+    let mut palette = [0; 4];
+    for (color, value) in palette.iter_mut().zip(&COLORS[offset..offset + 4]) {
+        *color = from_rgb555(*value);
+    }
+    palette
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    fn get_cartridge(title: &[u8], licensee: u8) -> Cartridge {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x14B] = licensee;
+        Cartridge::new(&rom, None)
+    }
+
+    #[test]
+    fn it_should_use_default_palette_for_other_licensees() {
+        let cartridge = get_cartridge(b"TETRIS", 0x00);
+
+        let palettes = get_compat_palettes(cartridge.header(), Buttons::default());
+
+        assert_eq!(palettes.bg, [0xFFFFFF, 0x7BFF31, 0x0063C6, 0x000000]);
+        assert_eq!(palettes.obj0, [0xFFFFFF, 0xFF8484, 0x943939, 0x000000]);
+    }
+
+    #[test]
+    fn it_should_find_palette_from_title_checksum() {
+        let cartridge = get_cartridge(b"TETRIS", 0x01);
+
+        let palettes = get_compat_palettes(cartridge.header(), Buttons::default());
+
+        assert_eq!(palettes.bg, [0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000]);
+    }
+
+    #[test]
+    fn it_should_use_fourth_letter_for_duplicate_checksums() {
+        let cartridge = get_cartridge(b"SUPER MARIOLAND", 0x01);
+
+        let mario = get_compat_palettes(cartridge.header(), Buttons::default());
+
+        assert_eq!(get_checksum_index(cartridge.header()), 66);
+        assert_eq!(mario.obj0[1], from_rgb555(COLORS[16]));
+    }
+
+    #[test]
+    fn it_should_select_palette_with_buttons() {
+        let buttons = Buttons {
+            left: true,
+            b: true,
+            ..Buttons::default()
+        };
+
+        let cartridge = get_cartridge(b"TETRIS", 0x01);
+
+        let palettes = get_compat_palettes(cartridge.header(), buttons);
+
+        assert_eq!(palettes.bg, [0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000]);
+    }
+}
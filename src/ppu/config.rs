@@ -1,4 +1,7 @@
-pub const VRAM_SIZE: usize = 0x9FFF - 0x8000 + 1;
+pub const VRAM_SIZE: usize = 2 * VRAM_BANK_SIZE;
+pub const VRAM_BANK_SIZE: usize = 0x2000;
+pub const TILES_PER_BANK: usize = 384;
+pub const PALETTE_RAM_SIZE: usize = 64;
 pub const OAM_SIZE: usize = 0xFE9F - 0xFE00 + 1;
 
 pub type Tile = [[u8; 8]; 8];
@@ -0,0 +1,55 @@
+#![allow(clippy::new_without_default)]
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BgPixel {
+    pub color: u8,
+    pub attributes: u8,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ObjectPixel {
+    pub color: u8,
+    pub flags: u8,
+    pub oam: u8,
+}
+
+pub struct Fetcher {
+    pub step: FetchStep,
+    pub dots: u8,
+    pub x: u8,
+    pub window: bool,
+    pub tile: u8,
+    pub attributes: u8,
+    pub row: [u8; 8],
+}
+
+impl Fetcher {
+    pub fn new() -> Fetcher {
+        Fetcher {
+            step: FetchStep::Tile,
+            dots: 0,
+            x: 0,
+            window: false,
+            tile: 0,
+            attributes: 0,
+            row: [0; 8],
+        }
+    }
+
+    pub fn reset(&mut self, window: bool) {
+        *self = Fetcher::new();
+        self.window = window;
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.step == FetchStep::Push
+    }
+}
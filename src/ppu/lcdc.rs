@@ -8,19 +8,24 @@ impl Ppu {
             eprintln!("forbiden tried to turn of lcd");
             return;
         }
-        if self.is_lcd_active() && value & 0b_1000_0000 == 0 {
+        let was_active = self.is_lcd_active();
+        self.lcdc = value;
+        if was_active && !self.is_lcd_active() {
             self.dot = 0;
             self.ly = 0;
             self.window_ly = 0;
             self.state = State::Mode0;
+            self.stat &= 0b1111_1100;
+            self.stat_line = false;
         }
-        if !self.is_lcd_active() && value & 0b_1000_0000 >= 1 {
+        if !was_active && self.is_lcd_active() {
             self.dot = 0;
             self.ly = 0;
             self.window_ly = 0;
-            self.state = State::Mode0;
+            self.wy_triggered = false;
+            self.switch_state(State::Mode2);
+            self.check_lcy_y();
         }
-        self.lcdc = value;
     }
 
     pub fn is_lcd_active(&mut self) -> bool {
@@ -1,42 +1,197 @@
 use crate::ppu::Ppu;
 
-use crate::ppu::config::WIDTH;
-use crate::ppu::{get_u32_color, Tile};
+use crate::ppu::config::State;
+use crate::ppu::config::{TILES_PER_BANK, VRAM_BANK_SIZE, WIDTH};
+use crate::ppu::get_u32_color;
 
-use super::config::HEIGHT;
-use super::object::{flip_tile_if_flag, flip_tile_if_flag_16, Object};
+use super::fetcher::{BgPixel, FetchStep, ObjectPixel};
+use super::object::Object;
+
+const STARTUP_DOTS: u8 = 6;
+const OBJECT_FETCH_DOTS: u8 = 6;
+const WINDOW_WRAP_WX: u8 = 166;
+const MAX_OBJECTS_PER_LINE: usize = 10;
 
 impl Ppu {
-    pub fn render(&mut self) {
-        if self.is_bg_window_active() {
-            self.render_back();
-            if self.window_visible() {
-                self.render_window();
-            }
+    pub fn start_rendering(&mut self) {
+        self.objects.clear();
+        self.build_objects_list();
+        self.line_objects = self.get_object_to_display();
+        self.line_objects.sort();
+        self.x = 0;
+        self.startup = STARTUP_DOTS;
+        self.discard = self.scx % 8;
+        self.bg_fifo.clear();
+        self.obj_fifo.clear();
+        self.fetcher.reset(false);
+        self.pending_object = None;
+        self.object_dots = 0;
+    }
+
+    pub fn render_dot(&mut self) {
+        if self.startup > 0 {
+            self.startup -= 1;
+            return;
+        }
+        if self.pending_object.is_some() {
+            self.fetch_object();
+            return;
+        }
+        self.tick_fetcher();
+        if self.bg_fifo.is_empty() {
+            return;
+        }
+        if self.discard > 0 {
+            self.bg_fifo.pop_front();
+            self.discard -= 1;
+            return;
+        }
+        if self.should_start_window() {
+            self.start_window();
+            self.tick_fetcher();
+            return;
+        }
+        self.pending_object = self.get_object_at_x();
+        if self.pending_object.is_some() {
+            self.fetch_object();
+            return;
+        }
+        self.push_pixel();
+    }
+
+    fn push_pixel(&mut self) {
+        let bg = self.bg_fifo.pop_front().unwrap_or_default();
+        let obj = self.obj_fifo.pop_front().unwrap_or_default();
+        let index = self.ly as usize * WIDTH + self.x as usize;
+        let pixel = if self.cgb {
+            self.mix_cgb_pixel(bg, obj)
         } else {
-            self.paint_white();
+            let shade = self.mix_dmg_pixel(bg.color, obj);
+            self.shade_buffer[index] = shade;
+            self.get_dmg_color(shade)
+        };
+        self.video_buffer[index] = pixel;
+        self.x += 1;
+        if self.x as usize == WIDTH {
+            self.hblank = true;
+            self.switch_state(State::Mode0);
         }
-        if self.is_obj_active() {
-            self.render_obj();
+    }
+
+    fn mix_dmg_pixel(&mut self, bg: u8, obj: ObjectPixel) -> u8 {
+        let bg = if self.is_bg_window_active() { bg } else { 0 };
+        if obj.color != 0 && self.is_obj_active() && !self.is_behind_bg(obj, bg) {
+            let palette = if obj.flags & 0b1_0000 != 0 { 2 } else { 1 };
+            palette << 2 | self.get_sprite_color(obj.color, obj.flags)
+        } else {
+            self.get_color_from_bg_palette(bg)
         }
     }
 
-    fn render_back(&mut self) {
-        for x in 0..(WIDTH / 8) {
-            let offset = self.get_tile_offset(x as u8, self.ly);
-            let index = self.get_base_index_data(offset);
+    fn get_dmg_color(&self, shade: u8) -> u32 {
+        let palette = match shade >> 2 {
+            0 => &self.palettes.bg,
+            1 => &self.palettes.obj0,
+            _ => &self.palettes.obj1,
+        };
+        get_u32_color(palette, shade)
+    }
 
-            let tile = self.tiles[index];
-            self.write_tile_in_video_buffer(&tile, x, self.ly as usize);
+    fn mix_cgb_pixel(&mut self, bg: BgPixel, obj: ObjectPixel) -> u32 {
+        let bg_priority = self.is_bg_window_active()
+            && bg.color != 0
+            && (bg.attributes & 0x80 != 0 || obj.flags & 0x80 != 0);
+        if obj.color != 0 && self.is_obj_active() && !bg_priority {
+            self.get_cgb_obj_color(obj)
+        } else {
+            self.get_cgb_bg_color(bg)
         }
     }
 
-    fn get_tile_offset(&mut self, x: u8, y: u8) -> u8 {
-        let base = self.get_base_index();
-        let x_offset = ((self.scx / 8) + x) & 0x1F;
-        let y_offset = y.wrapping_add(self.scy);
-        let offset = base + x_offset as usize + 32 * (y_offset / 8) as usize;
-        self.vram[offset]
+    fn is_behind_bg(&self, obj: ObjectPixel, bg: u8) -> bool {
+        obj.flags & 0x80 == 0x80 && bg != 0
+    }
+
+    fn tick_fetcher(&mut self) {
+        match self.fetcher.step {
+            FetchStep::Tile => {
+                self.fetcher.dots += 1;
+                if self.fetcher.dots == 2 {
+                    let offset = self.get_tile_offset();
+                    self.fetcher.tile = self.vram[offset];
+                    self.fetcher.attributes = if self.cgb {
+                        self.vram[VRAM_BANK_SIZE + offset]
+                    } else {
+                        0
+                    };
+                    self.next_fetch_step(FetchStep::DataLow);
+                }
+            }
+            FetchStep::DataLow => {
+                self.fetcher.dots += 1;
+                if self.fetcher.dots == 2 {
+                    self.next_fetch_step(FetchStep::DataHigh);
+                }
+            }
+            FetchStep::DataHigh => {
+                self.fetcher.dots += 1;
+                if self.fetcher.dots == 2 {
+                    self.fetcher.row = self.get_fetched_row();
+                    self.next_fetch_step(FetchStep::Push);
+                }
+            }
+            FetchStep::Push => {
+                if self.bg_fifo.is_empty() {
+                    let attributes = self.fetcher.attributes;
+                    self.bg_fifo
+                        .extend(self.fetcher.row.map(|color| BgPixel { color, attributes }));
+                    self.fetcher.x = self.fetcher.x.wrapping_add(1);
+                    self.next_fetch_step(FetchStep::Tile);
+                }
+            }
+        }
+    }
+
+    fn next_fetch_step(&mut self, step: FetchStep) {
+        self.fetcher.step = step;
+        self.fetcher.dots = 0;
+    }
+
+    fn get_fetched_row(&mut self) -> [u8; 8] {
+        let attributes = self.fetcher.attributes;
+        let mut index = self.get_base_index_data(self.fetcher.tile);
+        if attributes & 0b1000 != 0 {
+            index += TILES_PER_BANK;
+        }
+        let mut line = self.get_fetch_row();
+        if attributes & 0b0100_0000 != 0 {
+            line = 7 - line;
+        }
+        let mut row = self.tiles[index][line as usize];
+        if attributes & 0b0010_0000 != 0 {
+            row.reverse();
+        }
+        row
+    }
+
+    fn get_tile_offset(&mut self) -> usize {
+        if self.fetcher.window {
+            self.get_window_base_index()
+                + (self.fetcher.x & 0x1F) as usize
+                + 32 * (self.window_ly / 8) as usize
+        } else {
+            let x_offset = ((self.scx / 8).wrapping_add(self.fetcher.x)) & 0x1F;
+            let y_offset = self.ly.wrapping_add(self.scy);
+            self.get_base_index() + x_offset as usize + 32 * (y_offset / 8) as usize
+        }
+    }
+
+    fn get_fetch_row(&self) -> u8 {
+        if self.fetcher.window {
+            self.window_ly % 8
+        } else {
+            self.ly.wrapping_add(self.scy) % 8
+        }
     }
 
     fn get_base_index(&mut self) -> usize {
@@ -55,35 +210,6 @@ impl Ppu {
         }
     }
 
-    pub fn write_tile_in_video_buffer(&mut self, tile: &Tile, x: usize, y: usize) {
-        for xd in 0..8 {
-            let pixel = tile[y % 8][xd];
-            let color = self.get_color_from_bg_palette(pixel);
-            self.video_buffer[y * WIDTH + x * 8 + xd] = get_u32_color(color);
-            self.bg_trace[y * WIDTH + x * 8 + xd] = get_u32_color(color);
-        }
-    }
-
-    fn render_window(&mut self) {
-        for x in 0..(WIDTH / 8) {
-            let offset = self.get_window_tile_offset(x as u8, self.window_ly);
-            let index = self.get_base_index_data(offset);
-
-            let tile = self.tiles[index];
-            self.write_tile_in_video_buffer(
-                &tile,
-                (self.wx as usize + (x * 8)) / 8,
-                self.ly as usize,
-            );
-        }
-    }
-
-    fn get_window_tile_offset(&mut self, x: u8, y: u8) -> u8 {
-        let base = self.get_window_base_index();
-        let offset = base + x as usize + 32 * (y / 8) as usize;
-        self.vram[offset]
-    }
-
     fn get_window_base_index(&mut self) -> usize {
         if self.is_window_tilemap2() {
             0x1c00
@@ -92,110 +218,377 @@ impl Ppu {
         }
     }
 
-    fn render_obj(&mut self) {
-        let mut to_display = self.get_object_to_display();
-        to_display.sort();
-        for obj in to_display.iter().rev() {
-            self.render_object(obj);
+    fn should_start_window(&mut self) -> bool {
+        if self.fetcher.window || !self.is_window() || (!self.cgb && !self.is_bg_window_active()) {
+            return false;
+        }
+        if self.window_wrap && self.x == 0 {
+            return true;
         }
+        self.wy_triggered && self.x + 7 == self.wx.max(7)
     }
 
-    fn render_object(&mut self, obj: &Object) {
-        if self.is_obj_16() {
-            let tile = self.tiles[obj.index as usize & 0xFE];
-            let tile2 = self.tiles[obj.index as usize | 0x01];
-            let (sprite1, sprite2) = flip_tile_if_flag_16(tile, tile2, obj.flags);
-            self.render_obj_8(sprite2, obj, 8);
-            self.render_obj_8(sprite1, obj, 0);
-        } else {
-            let tile = self.tiles[obj.index as usize];
-            let sprite = flip_tile_if_flag(tile, obj.flags);
-            self.render_obj_8(sprite, obj, 0);
+    fn start_window(&mut self) {
+        self.bg_fifo.clear();
+        self.fetcher.reset(true);
+        self.window_drawn = true;
+        if self.wx < 7 {
+            self.discard = 7 - self.wx;
         }
+        self.window_wrap = self.wx == WINDOW_WRAP_WX;
     }
 
-    fn render_obj_8(&mut self, sprite: Tile, obj: &Object, y_offset: u8) {
-        let y: usize =
-            obj.y.wrapping_sub(16).wrapping_add(y_offset) as usize + (self.ly as usize % 8);
-        let x: usize = obj.x.wrapping_sub(8) as usize;
-        for xd in 0..8 {
-            if (x + xd) >= WIDTH || y >= HEIGHT {
-                continue;
+    fn get_object_at_x(&mut self) -> Option<Object> {
+        if !self.is_obj_active() {
+            return None;
+        }
+        let position = self
+            .line_objects
+            .iter()
+            .position(|obj| obj.x < WIDTH as u8 + 8 && obj.x.saturating_sub(8) == self.x)?;
+        Some(self.line_objects.remove(position))
+    }
+
+    fn fetch_object(&mut self) {
+        if !self.fetcher.is_ready() {
+            self.tick_fetcher();
+            if !self.fetcher.is_ready() {
+                return;
             }
-            let pixel = sprite[y % 8][(xd + x) % 8];
-            if pixel != 0 {
-                let color = self.get_sprite_color(pixel, obj.flags);
-                if obj.flags & 0x80 == 0x80 && self.is_bg_window_collision(x + xd, y) {
-                    continue;
-                }
-                self.video_buffer[y * WIDTH + x + xd] = get_u32_color(color);
+        }
+        self.object_dots += 1;
+        if self.object_dots < OBJECT_FETCH_DOTS {
+            return;
+        }
+        self.object_dots = 0;
+        if let Some(obj) = self.pending_object.take() {
+            self.merge_object(&obj);
+        }
+    }
+
+    fn merge_object(&mut self, obj: &Object) {
+        let row = self.get_object_row(obj);
+        let hidden = 8 - obj.x.min(8) as usize;
+        for (index, color) in row.iter().skip(hidden).enumerate() {
+            if index == self.obj_fifo.len() {
+                self.obj_fifo.push_back(ObjectPixel::default());
+            }
+            let current = self.obj_fifo[index];
+            if *color != 0 && (current.color == 0 || (self.cgb && obj.oam_position < current.oam)) {
+                self.obj_fifo[index] = ObjectPixel {
+                    color: *color,
+                    flags: obj.flags,
+                    oam: obj.oam_position,
+                };
             }
         }
     }
 
-    fn is_bg_window_collision(&mut self, x: usize, y: usize) -> bool {
-        let color = self.bg_trace[y * WIDTH + x];
-        color != get_u32_color(0)
+    fn get_object_row(&mut self, obj: &Object) -> [u8; 8] {
+        let height = if self.is_obj_16() { 16 } else { 8 };
+        let mut line = self.ly.wrapping_add(16).wrapping_sub(obj.y) % height;
+        if obj.flags & 0b0100_0000 != 0 {
+            line = height - 1 - line;
+        }
+        let mut index = if height == 16 {
+            ((obj.index & 0xFE) + line / 8) as usize
+        } else {
+            obj.index as usize
+        };
+        if self.cgb && obj.flags & 0b1000 != 0 {
+            index += TILES_PER_BANK;
+        }
+        let mut row = self.tiles[index][(line % 8) as usize];
+        if obj.flags & 0b0010_0000 != 0 {
+            row.reverse();
+        }
+        row
     }
 
     fn get_object_to_display(&mut self) -> Vec<Object> {
-        let mut retval: Vec<Object> = vec![];
         let size = self.is_obj_16();
-        let mut obj_iter = self.objects.iter();
-        let mut counter = 0;
-        loop {
-            if counter == 10 {
-                break;
-            }
-            let obj = obj_iter.next();
-            if let Some(object) = obj {
-                let y = object.y;
-                if is_object_visible(y, self.ly, size) {
-                    retval.push(*object);
-                    counter += 1;
-                }
-            } else {
-                break;
-            }
+        self.objects
+            .iter()
+            .filter(|obj| is_object_visible(obj.y, self.ly, size))
+            .take(MAX_OBJECTS_PER_LINE)
+            .copied()
+            .collect()
+    }
+}
+fn is_object_visible(y: u8, ly: u8, big_sprite: bool) -> bool {
+    let height = if big_sprite { 16 } else { 8 };
+    let line = ly as u16 + 16;
+    line >= y as u16 && line < y as u16 + height
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_mode3_length(ppu: &mut Ppu) -> u32 {
+        while ppu.state != State::Mode3 {
+            ppu.run_ppu();
+        }
+        let mut retval = 0;
+        while ppu.state == State::Mode3 {
+            ppu.run_ppu();
+            retval += 1;
         }
         retval
     }
 
-    fn paint_white(&mut self) {
-        let color = self.get_color_from_bg_palette(0);
-        for x in 0..(WIDTH) {
-            self.video_buffer[self.ly as usize * WIDTH + x] = get_u32_color(color);
+    fn get_ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF40, 0x93);
+        ppu
+    }
+
+    #[test]
+    fn it_should_take_172_dots_in_mode3() {
+        let mut ppu = get_ppu();
+
+        assert_eq!(get_mode3_length(&mut ppu), 172);
+    }
+
+    #[test]
+    fn it_should_add_fine_scroll_penalty() {
+        let mut ppu = get_ppu();
+        ppu.write(0xFF43, 0x13);
+
+        assert_eq!(get_mode3_length(&mut ppu), 175);
+    }
+
+    #[test]
+    fn it_should_add_window_penalty() {
+        let mut ppu = get_ppu();
+        ppu.write(0xFF40, 0xB3);
+        ppu.write(0xFF4B, 50);
+
+        assert_eq!(get_mode3_length(&mut ppu), 178);
+    }
+
+    fn get_window_ppu(wx: u8) -> Ppu {
+        let mut ppu = get_ppu();
+        ppu.write(0xFF40, 0xF3);
+        ppu.write(0xFF47, 0xE4);
+        ppu.write(0xFF4B, wx);
+        ppu.vram[0x1C00] = 1;
+        ppu.vram[16..32].fill(0xFF);
+        ppu.rebuild_tiles();
+        ppu
+    }
+
+    fn get_dark_pixels(ppu: &Ppu, ly: usize) -> Vec<usize> {
+        (0..WIDTH)
+            .filter(|x| ppu.video_buffer[ly * WIDTH + x] == ppu.palettes.bg[3])
+            .collect()
+    }
+
+    #[test]
+    fn it_should_scroll_background_by_pixel() {
+        let mut ppu = get_window_ppu(0);
+        ppu.write(0xFF40, 0x93);
+        ppu.vram[0x1801] = 1;
+        ppu.write(0xFF43, 3);
+
+        get_mode3_length(&mut ppu);
+
+        assert_eq!(get_dark_pixels(&ppu, 0), (5..13).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn it_should_place_window_at_wx_minus_7() {
+        let mut ppu = get_window_ppu(20);
+
+        get_mode3_length(&mut ppu);
+
+        assert_eq!(get_dark_pixels(&ppu, 0), (13..21).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn it_should_shift_window_when_wx_is_below_7() {
+        let mut ppu = get_window_ppu(3);
+
+        get_mode3_length(&mut ppu);
+
+        assert_eq!(get_dark_pixels(&ppu, 0), (0..4).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn it_should_span_next_line_when_wx_is_166() {
+        let mut ppu = get_window_ppu(166);
+
+        get_mode3_length(&mut ppu);
+        assert_eq!(get_dark_pixels(&ppu, 0), vec![159]);
+        ppu.write(0xFF4A, 0x80);
+        get_mode3_length(&mut ppu);
+
+        assert_eq!(get_dark_pixels(&ppu, 1), (0..8).collect::<Vec<usize>>());
+    }
+
+    fn write_object(ppu: &mut Ppu, position: usize, x: u8, tile: u8, flags: u8) {
+        let loc = 0xFE00 + position * 4;
+        ppu.write_oam(loc, 16);
+        ppu.write_oam(loc + 1, x);
+        ppu.write_oam(loc + 2, tile);
+        ppu.write_oam(loc + 3, flags);
+    }
+
+    fn get_sprite_ppu() -> Ppu {
+        let mut ppu = get_ppu();
+        ppu.write(0xFF47, 0xE4);
+        ppu.write(0xFF48, 0xE4);
+        ppu.write(0xFF49, 0x00);
+        ppu.vram[16..32].fill(0xFF);
+        ppu.vram[32..48].fill(0xFF);
+        ppu.rebuild_tiles();
+        ppu
+    }
+
+    #[test]
+    fn it_should_draw_only_first_10_sprites_of_line() {
+        let mut ppu = get_sprite_ppu();
+        for position in 0..10 {
+            write_object(&mut ppu, position, 0, 1, 0);
         }
+        write_object(&mut ppu, 10, 20, 1, 0);
+
+        get_mode3_length(&mut ppu);
+
+        assert!(get_dark_pixels(&ppu, 0).is_empty());
     }
-}
-fn is_object_visible(y: u8, ly: u8, big_sprite: bool) -> bool {
-    let adjust_ly = ly + 16;
-    if !is_sprite_in_visible_frame(y, big_sprite) {
-        return false;
+
+    #[test]
+    fn it_should_give_priority_to_lower_x() {
+        let mut ppu = get_sprite_ppu();
+        write_object(&mut ppu, 0, 12, 1, 0b1_0000);
+        write_object(&mut ppu, 1, 10, 1, 0);
+
+        get_mode3_length(&mut ppu);
+
+        assert_eq!(get_dark_pixels(&ppu, 0), (2..10).collect::<Vec<usize>>());
     }
-    if !big_sprite {
-        adjust_ly >= y && adjust_ly < (y + 8)
-    } else {
-        adjust_ly >= y && adjust_ly < (y + 16)
+
+    #[test]
+    fn it_should_give_priority_to_lower_oam_index() {
+        let mut ppu = get_sprite_ppu();
+        write_object(&mut ppu, 0, 10, 1, 0b1_0000);
+        write_object(&mut ppu, 1, 10, 1, 0);
+
+        get_mode3_length(&mut ppu);
+
+        assert!(get_dark_pixels(&ppu, 0).is_empty());
     }
-}
 
-fn is_sprite_in_visible_frame(y: u8, big_sprite: bool) -> bool {
-    if y > 160 {
-        return false;
+    #[test]
+    fn it_should_keep_color_0_transparent() {
+        let mut ppu = get_sprite_ppu();
+        write_object(&mut ppu, 0, 10, 0, 0);
+        write_object(&mut ppu, 1, 10, 1, 0);
+
+        get_mode3_length(&mut ppu);
+
+        assert_eq!(get_dark_pixels(&ppu, 0), (2..10).collect::<Vec<usize>>());
     }
-    if big_sprite && y.wrapping_add(16) < 16 {
-        return false;
+
+    #[test]
+    fn it_should_hide_sprite_behind_bg_colors_1_to_3() {
+        let mut ppu = get_sprite_ppu();
+        ppu.write(0xFF47, 0x00);
+        ppu.vram[0x1800] = 2;
+        write_object(&mut ppu, 0, 8, 1, 0x80);
+        write_object(&mut ppu, 1, 16, 1, 0x80);
+
+        get_mode3_length(&mut ppu);
+
+        assert_eq!(get_dark_pixels(&ppu, 0), (8..16).collect::<Vec<usize>>());
     }
-    if !big_sprite && y.wrapping_add(16) + 8 < 16 {
-        return false;
+
+    fn get_cgb_ppu() -> Ppu {
+        let mut ppu = get_ppu();
+        ppu.set_cgb(true);
+        ppu.write(0xFF68, 0x80 | 0x0E);
+        ppu.write(0xFF69, 0x1F);
+        ppu.write(0xFF69, 0x00);
+        ppu.write(0xFF6A, 0x80 | 0x06);
+        ppu.write(0xFF6B, 0xE0);
+        ppu.write(0xFF6B, 0x03);
+        ppu.write(0xFF6A, 0x80 | 0x0E);
+        ppu.write(0xFF6B, 0x00);
+        ppu.write(0xFF6B, 0x7C);
+        ppu
     }
-    true
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn it_should_use_cgb_bg_attributes() {
+        let mut ppu = get_cgb_ppu();
+        ppu.write(0xFF4F, 1);
+        ppu.write(0x9800, 0b1001);
+        for loc in 0x8010..0x8020 {
+            ppu.write(loc, 0xFF);
+        }
+        ppu.write(0xFF4F, 0);
+        ppu.write(0x9800, 1);
+
+        get_mode3_length(&mut ppu);
+
+        assert_eq!(ppu.video_buffer[0], 0xFF0000);
+        assert_eq!(ppu.video_buffer[7], 0xFF0000);
+        assert_eq!(ppu.video_buffer[8], 0xFFFFFF);
+    }
+
+    #[test]
+    fn it_should_give_cgb_priority_to_lower_oam_index() {
+        let mut ppu = get_cgb_ppu();
+        for loc in 0x8010..0x8020 {
+            ppu.write(loc, 0xFF);
+        }
+        write_object(&mut ppu, 0, 12, 1, 0);
+        write_object(&mut ppu, 1, 10, 1, 1);
+
+        get_mode3_length(&mut ppu);
+
+        assert_eq!(ppu.video_buffer[2], 0x0000FF);
+        assert_eq!(ppu.video_buffer[4], 0x00FF00);
+        assert_eq!(ppu.video_buffer[11], 0x00FF00);
+    }
+
+    #[test]
+    fn it_should_add_sprite_penalty() {
+        let mut ppu = get_ppu();
+        ppu.write_oam(0xFE00, 16);
+        ppu.write_oam(0xFE01, 8);
+        ppu.write_oam(0xFE02, 1);
+
+        let length = get_mode3_length(&mut ppu);
+
+        assert!((172 + 6..=172 + 11).contains(&length));
+    }
+
+    #[test]
+    fn it_should_apply_scroll_written_mid_scanline() {
+        let mut ppu = get_ppu();
+        ppu.write(0xFF47, 0xE4);
+        for tile in 0x1800..0x1820 {
+            ppu.vram[tile] = (tile % 2) as u8;
+        }
+        ppu.vram[16..32].fill(0xFF);
+        ppu.rebuild_tiles();
+        while ppu.state != State::Mode3 {
+            ppu.run_ppu();
+        }
+        for _ in 0..80 {
+            ppu.run_ppu();
+        }
+
+        ppu.write(0xFF43, 8);
+        get_mode3_length(&mut ppu);
+
+        assert_eq!(ppu.video_buffer[0], ppu.palettes.bg[0]);
+        assert_eq!(ppu.video_buffer[8], ppu.palettes.bg[3]);
+        assert_eq!(ppu.video_buffer[152], ppu.palettes.bg[0]);
+        assert_eq!(ppu.video_buffer[159], ppu.palettes.bg[0]);
+    }
 
     #[test]
     fn it_should_be_visible() {
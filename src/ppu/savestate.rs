@@ -0,0 +1,181 @@
+use crate::ppu::config::State;
+use crate::ppu::fetcher::{BgPixel, FetchStep, ObjectPixel};
+use crate::ppu::object::Object;
+use crate::ppu::Ppu;
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
+
+impl Saveable for Ppu {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.vblank);
+        state.write_bool(self.stat_int);
+        state.write_u32(self.dot);
+        state.write_bool(self.frame_drawn);
+        state.write_u8(self.window_ly);
+        state.write_bool(self.wy_triggered);
+        state.write_bool(self.window_drawn);
+        state.write_bool(self.window_wrap);
+        state.write_bool(self.stat_line);
+        state.write_bool(self.hblank);
+        state.write_u8(self.x);
+        state.write_u8(self.ly);
+        state.write_u8(self.lyc);
+        state.write_u8(match self.state {
+            State::Mode0 => 0,
+            State::Mode1 => 1,
+            State::Mode2 => 2,
+            State::Mode3 => 3,
+        });
+        self.write_pipeline(state);
+        state.write_bytes(&self.vram);
+        state.write_bytes(&self.oam);
+        state.write_bytes(&[self.vram_bank, self.bcps, self.ocps]);
+        state.write_bytes(&self.bg_palette_ram);
+        state.write_bytes(&self.obj_palette_ram);
+        state.write_bytes(&[
+            self.dma, self.lcdc, self.stat, self.scy, self.scx, self.wy, self.wx, self.bgp,
+            self.obp0, self.obp1,
+        ]);
+        for pixel in self.video_buffer.iter() {
+            state.write_u32(*pixel);
+        }
+        state.write_bytes(&self.shade_buffer);
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.vblank = state.read_bool()?;
+        self.stat_int = state.read_bool()?;
+        self.dot = state.read_u32()?;
+        self.frame_drawn = state.read_bool()?;
+        self.window_ly = state.read_u8()?;
+        self.wy_triggered = state.read_bool()?;
+        self.window_drawn = state.read_bool()?;
+        self.window_wrap = state.read_bool()?;
+        self.stat_line = state.read_bool()?;
+        self.hblank = state.read_bool()?;
+        self.x = state.read_u8()?;
+        self.ly = state.read_u8()?;
+        self.lyc = state.read_u8()?;
+        self.state = match state.read_u8()? {
+            0 => State::Mode0,
+            1 => State::Mode1,
+            2 => State::Mode2,
+            3 => State::Mode3,
+            _ => return Err(StateError::InvalidValue("ppu mode")),
+        };
+        self.read_pipeline(state)?;
+        state.read_bytes(&mut self.vram)?;
+        state.read_bytes(&mut self.oam)?;
+        let mut banks = [0; 3];
+        state.read_bytes(&mut banks)?;
+        [self.vram_bank, self.bcps, self.ocps] = [banks[0] & 1, banks[1] & 0xBF, banks[2] & 0xBF];
+        state.read_bytes(&mut self.bg_palette_ram)?;
+        state.read_bytes(&mut self.obj_palette_ram)?;
+        let mut registers = [0; 10];
+        state.read_bytes(&mut registers)?;
+        [
+            self.dma, self.lcdc, self.stat, self.scy, self.scx, self.wy, self.wx, self.bgp,
+            self.obp0, self.obp1,
+        ] = registers;
+        for pixel in self.video_buffer.iter_mut() {
+            *pixel = state.read_u32()?;
+        }
+        state.read_bytes(&mut self.shade_buffer)?;
+        self.rebuild_tiles();
+        self.objects.clear();
+        self.build_objects_list();
+        Ok(())
+    }
+}
+
+impl Ppu {
+    fn write_pipeline(&self, state: &mut StateWriter) {
+        state.write_u8(match self.fetcher.step {
+            FetchStep::Tile => 0,
+            FetchStep::DataLow => 1,
+            FetchStep::DataHigh => 2,
+            FetchStep::Push => 3,
+        });
+        state.write_u8(self.fetcher.dots);
+        state.write_u8(self.fetcher.x);
+        state.write_bool(self.fetcher.window);
+        state.write_u8(self.fetcher.tile);
+        state.write_u8(self.fetcher.attributes);
+        state.write_bytes(&self.fetcher.row);
+        state.write_u8(self.bg_fifo.len() as u8);
+        for pixel in self.bg_fifo.iter() {
+            state.write_u8(pixel.color);
+            state.write_u8(pixel.attributes);
+        }
+        state.write_u8(self.obj_fifo.len() as u8);
+        for pixel in self.obj_fifo.iter() {
+            state.write_u8(pixel.color);
+            state.write_u8(pixel.flags);
+            state.write_u8(pixel.oam);
+        }
+        state.write_u8(self.line_objects.len() as u8);
+        for obj in self.line_objects.iter() {
+            write_object(state, obj);
+        }
+        state.write_bool(self.pending_object.is_some());
+        if let Some(obj) = &self.pending_object {
+            write_object(state, obj);
+        }
+        state.write_u8(self.object_dots);
+        state.write_u8(self.startup);
+        state.write_u8(self.discard);
+    }
+
+    fn read_pipeline(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.fetcher.step = match state.read_u8()? {
+            0 => FetchStep::Tile,
+            1 => FetchStep::DataLow,
+            2 => FetchStep::DataHigh,
+            3 => FetchStep::Push,
+            _ => return Err(StateError::InvalidValue("ppu fetcher step")),
+        };
+        self.fetcher.dots = state.read_u8()?;
+        self.fetcher.x = state.read_u8()?;
+        self.fetcher.window = state.read_bool()?;
+        self.fetcher.tile = state.read_u8()?;
+        self.fetcher.attributes = state.read_u8()?;
+        state.read_bytes(&mut self.fetcher.row)?;
+        self.bg_fifo.clear();
+        for _ in 0..state.read_u8()? {
+            self.bg_fifo.push_back(BgPixel {
+                color: state.read_u8()?,
+                attributes: state.read_u8()?,
+            });
+        }
+        self.obj_fifo.clear();
+        for _ in 0..state.read_u8()? {
+            self.obj_fifo.push_back(ObjectPixel {
+                color: state.read_u8()?,
+                flags: state.read_u8()?,
+                oam: state.read_u8()?,
+            });
+        }
+        self.line_objects.clear();
+        for _ in 0..state.read_u8()? {
+            self.line_objects.push(read_object(state)?);
+        }
+        self.pending_object = match state.read_bool()? {
+            true => Some(read_object(state)?),
+            false => None,
+        };
+        self.object_dots = state.read_u8()?;
+        self.startup = state.read_u8()?;
+        self.discard = state.read_u8()?;
+        Ok(())
+    }
+}
+
+fn write_object(state: &mut StateWriter, obj: &Object) {
+    state.write_bytes(&[obj.x, obj.y, obj.index, obj.flags, obj.oam_position]);
+}
+
+fn read_object(state: &mut StateReader) -> Result<Object, StateError> {
+    let mut bytes = [0; 5];
+    state.read_bytes(&mut bytes)?;
+    let [x, y, index, flags, oam_position] = bytes;
+    Ok(Object::new(x, y, index, flags, oam_position))
+}
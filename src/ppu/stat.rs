@@ -5,7 +5,8 @@ use crate::ppu::Ppu;
 impl Ppu {
     pub fn write_stat(&mut self, value: u8) {
         let before = self.stat & 0b0000_0111;
-        self.stat = (value & 0b1111_1000) | before;
+        self.stat = (value & 0b0111_1000) | before;
+        self.update_stat_line();
     }
 
     pub fn update_stat(&mut self, new_state: &State) {
@@ -23,21 +24,20 @@ impl Ppu {
             }
             Mode0 => self.stat &= 0b1111_1100,
         }
-        self.handle_stat_interrupt(new_state);
+        self.update_stat_line();
     }
 
-    fn handle_stat_interrupt(&mut self, new_state: &State) {
-        let before = self.stat_int;
-        if (self.stat & 0x20 > 0 && *new_state == Mode2)
-            || (self.stat & 0b_0001_0000 > 0 && *new_state == Mode1)
-            || (self.stat & 0b_0000_1000 > 0 && *new_state == Mode0)
-        {
+    pub fn update_stat_line(&mut self) {
+        let mode = self.stat & 0b11;
+        let line = self.is_lcd_active()
+            && ((self.stat & 0b_0100_0000 > 0 && self.stat & 0b100 > 0)
+                || (self.stat & 0b_0010_0000 > 0 && mode == 2)
+                || (self.stat & 0b_0001_0000 > 0 && mode == 1)
+                || (self.stat & 0b_0000_1000 > 0 && mode == 0));
+        if line && !self.stat_line {
             self.stat_int = true;
         }
-        if !before && self.stat_int {
-        } else {
-            self.stat_int = false
-        }
+        self.stat_line = line;
     }
 }
 
@@ -85,7 +85,7 @@ mod tests {
     fn it_should_not_trigger_stat_interrupt_mode0() {
         let mut ppu = Ppu::new();
 
-        ppu.stat_int = true;
+        ppu.stat_line = true;
         ppu.stat = 0x8;
         let new_state = Mode0;
         ppu.update_stat(&new_state);
@@ -3,84 +3,106 @@ use crate::ppu::State;
 use crate::ppu::State::{Mode0, Mode1, Mode2, Mode3};
 
 use super::config::HEIGHT;
-use super::config::WIDTH;
+
+const DOTS_PER_LINE: u32 = 456;
+const OAM_SCAN_DOTS: u32 = 80;
+const LINES_PER_FRAME: u8 = 154;
 
 impl Ppu {
     pub fn run_ppu(&mut self) {
-        self.dot += 1;
         match self.state {
-            Mode0 => {
-                if self.dot % 456 == 0 {
-                    self.scanline_drawn = false;
-                    self.increment_ly();
-                    if self.ly < 143 {
-                        self.switch_state(Mode2);
-                    } else {
-                        self.frame_drawn = true;
-                        self.switch_state(Mode1);
-                        self.vblank = true;
-                    }
-                } else if self.dot < 50 {
-                    self.switch_state(Mode2);
-                }
-            }
-            Mode1 => {
-                if self.dot <= 70224 {
-                    if self.dot % 456 == 0 {
-                        self.increment_ly();
-                    }
-                } else {
-                    self.ly = 0;
-                    self.window_ly = 0;
-                    self.dot = 0;
-                    self.state = Mode2;
-                }
-            }
             Mode2 => {
-                if self.dot % 456 >= 80 {
-                    self.objects.clear();
-                    self.build_objects_list();
-                    self.switch_state(Mode3);
-                }
-            }
-            Mode3 => {
-                if !self.scanline_drawn {
-                    self.render();
-                    self.scanline_drawn = true;
+                if self.ly == self.wy {
+                    self.wy_triggered = true;
                 }
-                if self.dot % 456 >= 80 + 172 {
-                    self.switch_state(Mode0);
+                if self.dot == OAM_SCAN_DOTS - 1 {
+                    self.start_rendering();
+                    self.switch_state(Mode3);
                 }
             }
+            Mode3 => self.render_dot(),
+            Mode0 | Mode1 => {}
+        }
+        self.dot += 1;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.next_line();
         }
     }
 
-    fn switch_state(&mut self, state: State) {
-        self.update_stat(&state);
-        self.state = state;
-    }
-
-    fn increment_ly(&mut self) {
-        if self.window_visible() {
+    fn next_line(&mut self) {
+        if self.window_drawn {
             self.window_ly += 1;
+            self.window_drawn = false;
         }
         self.ly += 1;
+        if self.ly == HEIGHT as u8 {
+            self.frame_drawn = true;
+            self.vblank = true;
+            self.switch_state(Mode1);
+        } else if self.ly == LINES_PER_FRAME {
+            self.ly = 0;
+            self.window_ly = 0;
+            self.wy_triggered = false;
+            self.window_wrap = false;
+            self.switch_state(Mode2);
+        } else if self.ly < HEIGHT as u8 {
+            self.switch_state(Mode2);
+        }
         self.check_lcy_y();
     }
 
-    pub fn window_visible(&mut self) -> bool {
-        self.is_window()
-            && self.wx < WIDTH as u8 + 7
-            && self.wy < HEIGHT as u8
-            && self.wy <= self.ly
+    pub fn switch_state(&mut self, state: State) {
+        self.update_stat(&state);
+        self.state = state;
     }
 
-    fn check_lcy_y(&mut self) {
-        self.stat_int_ly = self.lyc == self.ly && (self.stat & 0b_0100_0000) > 0;
+    pub fn check_lcy_y(&mut self) {
         if self.lyc == self.ly {
             self.stat |= 0b100;
         } else {
             self.stat &= !0b100;
         }
+        self.update_stat_line();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_draw_a_frame_every_70224_dots() {
+        let mut ppu = Ppu::new();
+        let mut dots = 0;
+        while !ppu.frame_drawn {
+            ppu.run_ppu();
+            dots += 1;
+        }
+        ppu.frame_drawn = false;
+        let start = dots;
+        while !ppu.frame_drawn {
+            ppu.run_ppu();
+            dots += 1;
+        }
+
+        assert_eq!(start, 144 * 456);
+        assert_eq!(dots - start, 70224);
+    }
+
+    #[test]
+    fn it_should_trigger_lyc_interrupt() {
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF41, 0x40);
+        ppu.write(0xFF45, 2);
+        for _ in 0..2 * 456 - 1 {
+            ppu.run_ppu();
+        }
+        assert!(!ppu.stat_int);
+
+        ppu.run_ppu();
+
+        assert!(ppu.stat_int);
+        assert_eq!(ppu.read(0xFF41) & 0b100, 0b100);
     }
 }
@@ -1,6 +1,6 @@
 use crate::ppu::Ppu;
 
-use crate::ppu::config::State;
+use crate::ppu::config::{State, TILES_PER_BANK, VRAM_BANK_SIZE};
 
 use crate::ppu::object::Object;
 
@@ -9,7 +9,7 @@ impl Ppu {
         match loc {
             0x8000..=0x9FFF => {
                 if self.state != State::Mode3 || !self.is_lcd_active() {
-                    self.vram[loc - 0x8000]
+                    self.vram[self.get_vram_offset(loc)]
                 } else {
                     0xFF
                 }
@@ -24,7 +24,7 @@ impl Ppu {
             }
 
             0xFF40 => self.lcdc,
-            0xFF41 => self.stat,
+            0xFF41 => self.stat | 0x80,
             0xFF42 => self.scy,
             0xFF43 => self.scx,
             0xFF4A => self.wy,
@@ -38,29 +38,32 @@ impl Ppu {
             0xFF47 => self.bgp,
             0xFF48 => self.obp0,
             0xFF49 => self.obp1,
+
+            0xFF4F if self.cgb => 0xFE | self.vram_bank,
+            0xFF68 if self.cgb => self.bcps | 0x40,
+            0xFF69 if self.cgb => self.bg_palette_ram[(self.bcps & 0x3F) as usize],
+            0xFF6A if self.cgb => self.ocps | 0x40,
+            0xFF6B if self.cgb => self.obj_palette_ram[(self.ocps & 0x3F) as usize],
             _ => 0xFF,
         }
     }
+
+    fn get_vram_offset(&self, loc: usize) -> usize {
+        self.vram_bank as usize * VRAM_BANK_SIZE + loc - 0x8000
+    }
     pub fn write_oam(&mut self, loc: usize, value: u8) {
         self.oam[loc - 0xFE00] = value;
     }
 
     pub fn build_objects_list(&mut self) {
-        let mut iter = self.oam.iter().peekable();
-        let mut counter = 0;
-        loop {
-            if iter.peek().is_none() {
-                break;
-            }
-            let y = iter.next().unwrap();
-            let x = iter.next().unwrap();
-            let index = iter.next().unwrap();
-            let flags = iter.next().unwrap();
-            if !(*x == 0 && *y == 0 && *index == 0 && *flags == 0) {
-                self.objects
-                    .push(Object::new(*x, *y, *index, *flags, counter));
-                counter += 1;
-            }
+        for (position, entry) in self.oam.chunks(4).enumerate() {
+            self.objects.push(Object::new(
+                entry[1],
+                entry[0],
+                entry[2],
+                entry[3],
+                position as u8,
+            ));
         }
     }
 
@@ -68,10 +71,7 @@ impl Ppu {
         match loc {
             0x8000..=0x9FFF => {
                 if self.state != State::Mode3 || !self.is_lcd_active() {
-                    self.vram[loc - 0x8000] = value;
-                    if loc < 0x97FF {
-                        self.write_tiles(loc - 0x8000);
-                    }
+                    self.write_vram(loc, value);
                 }
             }
             0xFE00..=0xFE9F => {
@@ -88,20 +88,55 @@ impl Ppu {
             0xFF4A => self.wy = value,
             0xFF4B => self.wx = value,
             0xFF44 => {}
-            0xFF45 => self.lyc = value,
+            0xFF45 => {
+                self.lyc = value;
+                if self.is_lcd_active() {
+                    self.check_lcy_y();
+                }
+            }
             0xFF46 => self.dma = value,
 
             0xFF47 => self.bgp = value,
             0xFF48 => self.obp0 = value,
             0xFF49 => self.obp1 = value,
+
+            0xFF4F if self.cgb => self.vram_bank = value & 1,
+            0xFF68 if self.cgb => self.bcps = value & 0xBF,
+            0xFF69 if self.cgb => {
+                self.bg_palette_ram[(self.bcps & 0x3F) as usize] = value;
+                self.bcps = increment_palette_index(self.bcps);
+            }
+            0xFF6A if self.cgb => self.ocps = value & 0xBF,
+            0xFF6B if self.cgb => {
+                self.obj_palette_ram[(self.ocps & 0x3F) as usize] = value;
+                self.ocps = increment_palette_index(self.ocps);
+            }
             _ => {}
         }
     }
 
+    pub fn write_vram(&mut self, loc: usize, value: u8) {
+        let offset = self.get_vram_offset(loc);
+        self.vram[offset] = value;
+        self.write_tiles(offset);
+    }
+
+    pub fn rebuild_tiles(&mut self) {
+        for bank in 0..2 {
+            for loc in (0..0x1800).step_by(2) {
+                self.write_tiles(bank * VRAM_BANK_SIZE + loc);
+            }
+        }
+    }
+
     fn write_tiles(&mut self, loc: usize) {
-        let normalized_loc = loc & 0xFFFE;
-        let tile_loc = loc / 16;
-        let row_loc = (loc % 16) / 2;
+        let bank_loc = loc % VRAM_BANK_SIZE;
+        if bank_loc >= 0x1800 {
+            return;
+        }
+        let normalized_loc = loc & !1;
+        let tile_loc = (loc / VRAM_BANK_SIZE) * TILES_PER_BANK + bank_loc / 16;
+        let row_loc = (bank_loc % 16) / 2;
 
         let byte1 = self.vram[normalized_loc];
         let byte2 = self.vram[normalized_loc + 1];
@@ -119,3 +154,10 @@ impl Ppu {
         }
     }
 }
+
+fn increment_palette_index(spec: u8) -> u8 {
+    if spec & 0x80 == 0 {
+        return spec;
+    }
+    0x80 | (spec.wrapping_add(1) & 0x3F)
+}
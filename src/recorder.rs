@@ -0,0 +1,142 @@
+use crate::cli::Options;
+use game_boy::wav::WavWriter;
+use std::{fs::File, io::BufWriter, path::Path};
+
+type FileWriter = WavWriter<BufWriter<File>>;
+
+pub struct Recorder {
+    filename: String,
+    mixed: FileWriter,
+    channels: Option<Vec<FileWriter>>,
+}
+
+impl Recorder {
+    // The channel samples are only kept by the APU once asked with
+    // set_record_channels.
+    pub fn start(filename: &str, sample_rate: u32, channels: bool) -> Result<Recorder, String> {
+        let mixed = create_writer(filename, 2, sample_rate)?;
+        let channels = match channels {
+            true => Some(
+                (1..=4)
+                    .map(|channel| {
+                        create_writer(&get_channel_filename(filename, channel), 1, sample_rate)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            false => None,
+        };
+        Ok(Recorder {
+            filename: String::from(filename),
+            mixed,
+            channels,
+        })
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn record(&mut self, samples: &[f32], channel_samples: &[[f32; 4]]) -> Result<(), String> {
+        self.mixed
+            .write_samples(samples)
+            .map_err(|err| format!("cannot write {}: {}", self.filename, err))?;
+        let Some(writers) = &mut self.channels else {
+            return Ok(());
+        };
+        for (index, writer) in writers.iter_mut().enumerate() {
+            let channel: Vec<f32> = channel_samples.iter().map(|sample| sample[index]).collect();
+            writer
+                .write_samples(&channel)
+                .map_err(|err| format!("cannot write channel {} recording: {}", index + 1, err))?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), String> {
+        let error = |err| format!("cannot write {}: {}", self.filename, err);
+        self.mixed.finish().map_err(error)?;
+        for writer in self.channels.into_iter().flatten() {
+            writer.finish().map_err(error)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn record_audio(
+    samples: &[f32],
+    channel_samples: &[[f32; 4]],
+    recorder: &mut Option<Recorder>,
+) {
+    let Some(active) = recorder else {
+        return;
+    };
+    if let Err(err) = active.record(samples, channel_samples) {
+        eprintln!("Error while recording: {}", err);
+        // Keeps what was recorded readable.
+        if let Some(active) = recorder.take() {
+            let filename = String::from(active.filename());
+            match active.finish() {
+                Ok(()) => println!("Sound recorded in {}", filename),
+                Err(err) => eprintln!("Error while recording: {}", err),
+            }
+        }
+    }
+}
+
+pub fn toggle_recording(sample_rate: u32, options: &Options, recorder: &mut Option<Recorder>) {
+    if let Some(active) = recorder.take() {
+        let filename = String::from(active.filename());
+        match active.finish() {
+            Ok(()) => println!("Sound recorded in {}", filename),
+            Err(err) => eprintln!("Error while recording: {}", err),
+        }
+        return;
+    }
+    let filename = match &options.wav {
+        Some(filename) => filename.clone(),
+        None => format!("{}.wav", options.rom),
+    };
+    match Recorder::start(&filename, sample_rate, options.wav_channels) {
+        Ok(active) => {
+            println!("Recording sound in {}", filename);
+            *recorder = Some(active);
+        }
+        Err(err) => eprintln!("Error while recording: {}", err),
+    }
+}
+
+fn create_writer(filename: &str, channels: u16, sample_rate: u32) -> Result<FileWriter, String> {
+    let file =
+        File::create(filename).map_err(|err| format!("cannot create {}: {}", filename, err))?;
+    WavWriter::new(BufWriter::new(file), channels, sample_rate)
+        .map_err(|err| format!("cannot write {}: {}", filename, err))
+}
+
+// music.wav is split in music.ch1.wav to music.ch4.wav
+pub fn get_channel_filename(filename: &str, channel: usize) -> String {
+    let path = Path::new(filename);
+    let stem = path.with_extension("");
+    match path.extension() {
+        Some(extension) => format!(
+            "{}.ch{}.{}",
+            stem.display(),
+            channel,
+            extension.to_string_lossy()
+        ),
+        None => format!("{}.ch{}", stem.display(), channel),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_name_channel_files() {
+        assert_eq!(
+            get_channel_filename("out/music.wav", 2),
+            "out/music.ch2.wav"
+        );
+        assert_eq!(get_channel_filename("music", 4), "music.ch4");
+    }
+}
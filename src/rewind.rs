@@ -0,0 +1,202 @@
+use std::collections::VecDeque;
+
+use crate::emulator::Emulator;
+
+pub const REWIND_INTERVAL: u32 = 4;
+pub const REWIND_CAPACITY: usize = 600;
+
+pub struct Rewind {
+    deltas: VecDeque<Vec<u8>>,
+    last: Vec<u8>,
+    interval: u32,
+    capacity: usize,
+    frame: u32,
+}
+
+impl Rewind {
+    pub fn new(interval: u32, capacity: usize) -> Rewind {
+        Rewind {
+            deltas: VecDeque::with_capacity(capacity),
+            last: vec![],
+            interval,
+            capacity,
+            frame: 0,
+        }
+    }
+
+    pub fn record(&mut self, emulator: &Emulator) {
+        self.frame += 1;
+        if self.frame < self.interval {
+            return;
+        }
+        self.frame = 0;
+        self.push(emulator.save_state());
+    }
+
+    pub fn step_back(&mut self, emulator: &mut Emulator) -> bool {
+        match self.pop() {
+            Some(state) => {
+                self.frame = 0;
+                emulator.load_state(&state).is_ok()
+            }
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self.last.is_empty() {
+            true => 0,
+            false => self.deltas.len() + 1,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last.is_empty()
+    }
+
+    fn push(&mut self, state: Vec<u8>) {
+        if self.last.len() == state.len() {
+            if self.deltas.len() == self.capacity {
+                self.deltas.pop_front();
+            }
+            self.deltas.push_back(compress(&xor(&state, &self.last)));
+        } else {
+            self.deltas.clear();
+        }
+        self.last = state;
+    }
+
+    // The newest snapshot comes first, the emulator being a few frames past it.
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        if self.last.is_empty() {
+            return None;
+        }
+        let state = self.last.clone();
+        self.last = match self.deltas.pop_back().map(|delta| decompress(&delta)) {
+            Some(delta) if delta.len() == state.len() => xor(&state, &delta),
+            _ => {
+                self.deltas.clear();
+                vec![]
+            }
+        };
+        Some(state)
+    }
+}
+
+fn xor(state: &[u8], previous: &[u8]) -> Vec<u8> {
+    state.iter().zip(previous).map(|(a, b)| a ^ b).collect()
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut retval = vec![];
+    let mut iter = data.iter().peekable();
+    while let Some(byte) = iter.next() {
+        if *byte != 0 {
+            retval.push(*byte);
+            continue;
+        }
+        let mut run: u16 = 1;
+        while run < u16::MAX && iter.peek() == Some(&&0) {
+            iter.next();
+            run += 1;
+        }
+        retval.push(0);
+        retval.extend_from_slice(&run.to_le_bytes());
+    }
+    retval
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut retval = vec![];
+    let mut iter = data.iter();
+    while let Some(byte) = iter.next() {
+        if *byte != 0 {
+            retval.push(*byte);
+            continue;
+        }
+        let lo = *iter.next().unwrap_or(&0);
+        let hi = *iter.next().unwrap_or(&0);
+        let run = u16::from_le_bytes([lo, hi]);
+        retval.resize(retval.len() + run as usize, 0);
+    }
+    retval
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::EmulatorOptions;
+
+    #[test]
+    fn it_should_compress_and_decompress() {
+        let mut data = vec![0; 70_000];
+        data[3] = 5;
+        data[69_999] = 1;
+
+        let compressed = compress(&data);
+
+        assert!(compressed.len() < 20);
+        assert_eq!(decompress(&compressed), data);
+    }
+
+    #[test]
+    fn it_should_step_back_in_order() {
+        let mut rewind = Rewind::new(1, 10);
+        rewind.push(vec![1, 2, 3]);
+        rewind.push(vec![1, 5, 3]);
+        rewind.push(vec![7, 5, 3]);
+
+        assert_eq!(rewind.pop(), Some(vec![7, 5, 3]));
+        assert_eq!(rewind.pop(), Some(vec![1, 5, 3]));
+        assert_eq!(rewind.pop(), Some(vec![1, 2, 3]));
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn it_should_drop_oldest_snapshot() {
+        let mut rewind = Rewind::new(1, 2);
+        rewind.push(vec![1]);
+        rewind.push(vec![2]);
+        rewind.push(vec![3]);
+        rewind.push(vec![4]);
+
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.pop(), Some(vec![4]));
+        assert_eq!(rewind.pop(), Some(vec![3]));
+        assert_eq!(rewind.pop(), Some(vec![2]));
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn it_should_restore_the_frame() {
+        let mut emulator = Emulator::new(&[0; 0x8000], EmulatorOptions::default());
+        let mut rewind = Rewind::new(1, 10);
+        emulator.run_frame();
+        rewind.record(&emulator);
+        let frame = emulator.framebuffer().to_vec();
+        emulator.cpu_mut().memory.ppu.video_buffer.fill(0x123456);
+
+        assert!(rewind.step_back(&mut emulator));
+        assert_eq!(emulator.framebuffer(), &frame[..]);
+    }
+
+    #[test]
+    fn it_should_restore_emulator() {
+        let mut emulator = Emulator::new(&[0; 0x8000], EmulatorOptions::default());
+        let mut rewind = Rewind::new(1, 10);
+        rewind.record(&emulator);
+        emulator.cpu_mut().reg.a = 0x10;
+        rewind.record(&emulator);
+        emulator.cpu_mut().reg.a = 0x20;
+        rewind.record(&emulator);
+        emulator.cpu_mut().reg.a = 0x30;
+
+        assert!(rewind.step_back(&mut emulator));
+        assert_eq!(emulator.cpu().reg.a, 0x20);
+        assert!(rewind.step_back(&mut emulator));
+        assert_eq!(emulator.cpu().reg.a, 0x10);
+        assert!(rewind.step_back(&mut emulator));
+        assert_eq!(emulator.cpu().reg.a, 0x01);
+        assert!(!rewind.step_back(&mut emulator));
+    }
+}
@@ -0,0 +1,258 @@
+#![allow(clippy::new_without_default)]
+
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+
+use crate::cpu::Cpu;
+
+const MAGIC: [u8; 4] = *b"GBSS";
+pub const STATE_VERSION: u16 = 12;
+
+#[derive(Debug)]
+pub enum StateError {
+    InvalidHeader,
+    UnsupportedVersion(u16),
+    RomMismatch,
+    Truncated,
+    InvalidValue(&'static str),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::InvalidHeader => write!(f, "not a save state file"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::RomMismatch => write!(f, "save state was made for a different rom"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::InvalidValue(what) => write!(f, "invalid value for {}", what),
+            StateError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for StateError {
+    fn from(err: std::io::Error) -> StateError {
+        StateError::Io(err)
+    }
+}
+
+pub trait Saveable {
+    fn write_state(&self, state: &mut StateWriter);
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { data: vec![] }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u128(&mut self, value: u128) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, values: &[u8]) {
+        self.data.extend_from_slice(values);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data, position: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.position + len > self.data.len() {
+            return Err(StateError::Truncated);
+        }
+        let retval = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(retval)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_u128(&mut self) -> Result<u128, StateError> {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(self.take(16)?);
+        Ok(u128::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self, target: &mut [u8]) -> Result<(), StateError> {
+        target.copy_from_slice(self.take(target.len())?);
+        Ok(())
+    }
+}
+
+pub fn save_state(cpu: &Cpu, frame: u64) -> Vec<u8> {
+    let mut state = StateWriter::new();
+    state.write_bytes(&MAGIC);
+    state.write_u16(STATE_VERSION);
+    state.write_u32(cpu.memory.rom_checksum());
+    state.write_u64(frame);
+    cpu.write_state(&mut state);
+    state.into_bytes()
+}
+
+// Returns the frame counter of the state. A state that fails to load leaves
+// the cpu as it was.
+pub fn load_state(cpu: &mut Cpu, data: &[u8]) -> Result<u64, StateError> {
+    let mut state = StateReader::new(data);
+    let mut magic = [0; 4];
+    state.read_bytes(&mut magic)?;
+    if magic != MAGIC {
+        return Err(StateError::InvalidHeader);
+    }
+    let version = state.read_u16()?;
+    if version != STATE_VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    if state.read_u32()? != cpu.memory.rom_checksum() {
+        return Err(StateError::RomMismatch);
+    }
+    let frame = state.read_u64()?;
+    let mut backup = StateWriter::new();
+    cpu.write_state(&mut backup);
+    if let Err(err) = cpu.read_state(&mut state) {
+        cpu.read_state(&mut StateReader::new(&backup.into_bytes()))
+            .expect("the state before the load is valid");
+        return Err(err);
+    }
+    Ok(frame)
+}
+
+pub fn slot_path(rom_filename: &str, slot: u8) -> String {
+    format!("{}.ss{}", rom_filename, slot)
+}
+
+pub fn write_slot(rom_filename: &str, slot: u8, data: &[u8]) -> Result<(), StateError> {
+    let mut file = File::create(slot_path(rom_filename, slot))?;
+    file.write_all(data)?;
+    Ok(())
+}
+
+pub fn read_slot(rom_filename: &str, slot: u8) -> Result<Vec<u8>, StateError> {
+    let mut file = File::open(slot_path(rom_filename, slot))?;
+    let mut data = vec![];
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_restore_registers_and_memory() {
+        let mut cpu = Cpu::new();
+        cpu.reg.a = 0x42;
+        cpu.reg.sp = 0xC100;
+        cpu.memory.pc = 0x150;
+        cpu.memory.write(0xC010, 0x99);
+        cpu.memory.write(0xFF80, 0x12);
+        let data = save_state(&cpu, 0);
+
+        let mut restored = Cpu::new();
+        assert_eq!(load_state(&mut restored, &data).unwrap(), 0);
+
+        assert_eq!(restored.reg.a, 0x42);
+        assert_eq!(restored.reg.sp, 0xC100);
+        assert_eq!(restored.memory.pc, 0x150);
+        assert_eq!(restored.memory.read(0xC010), 0x99);
+        assert_eq!(restored.memory.read(0xFF80), 0x12);
+    }
+
+    #[test]
+    fn it_should_refuse_state_for_other_rom() {
+        let cpu = Cpu::new();
+        let mut data = save_state(&cpu, 0);
+        data[6] = data[6].wrapping_add(1);
+
+        let mut restored = Cpu::new();
+        let res = load_state(&mut restored, &data);
+
+        assert!(matches!(res, Err(StateError::RomMismatch)));
+    }
+
+    #[test]
+    fn it_should_refuse_unknown_version() {
+        let cpu = Cpu::new();
+        let mut data = save_state(&cpu, 0);
+        data[4] = 0xFF;
+
+        let mut restored = Cpu::new();
+        let res = load_state(&mut restored, &data);
+
+        assert!(matches!(res, Err(StateError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn it_should_refuse_truncated_state() {
+        let cpu = Cpu::new();
+        let data = save_state(&cpu, 0);
+
+        let mut restored = Cpu::new();
+        let res = load_state(&mut restored, &data[..data.len() / 2]);
+
+        assert!(matches!(res, Err(StateError::Truncated)));
+    }
+}
@@ -0,0 +1,102 @@
+#![allow(clippy::new_without_default)]
+
+use crate::savestate::{Saveable, StateError, StateReader, StateWriter};
+
+const TRANSFER_CYCLES: u16 = 8 * 128;
+
+pub struct Serial {
+    pub data: u8,
+    control: u8,
+    counter: u16,
+    output: Vec<u8>,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            data: 0,
+            control: 0,
+            counter: 0,
+            output: vec![],
+        }
+    }
+
+    pub fn read_control(&self) -> u8 {
+        self.control | 0x7E
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        self.control = value & 0x81;
+        if self.control == 0x81 {
+            self.counter = TRANSFER_CYCLES;
+        } else {
+            self.counter = 0;
+        }
+    }
+
+    pub fn tick(&mut self) -> bool {
+        if self.counter == 0 {
+            return false;
+        }
+        self.counter -= 1;
+        if self.counter > 0 {
+            return false;
+        }
+        self.output.push(self.data);
+        self.data = 0xFF;
+        self.control &= 0x7F;
+        true
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+}
+
+impl Saveable for Serial {
+    fn write_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.data);
+        state.write_u8(self.control);
+        state.write_u16(self.counter);
+    }
+
+    fn read_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.data = state.read_u8()?;
+        self.control = state.read_u8()?;
+        self.counter = state.read_u16()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_send_byte_after_transfer() {
+        let mut serial = Serial::new();
+        serial.data = b'P';
+        serial.write_control(0x81);
+
+        for _ in 1..TRANSFER_CYCLES {
+            assert!(!serial.tick());
+        }
+
+        assert!(serial.tick());
+        assert_eq!(serial.take_output(), vec![b'P']);
+        assert_eq!(serial.data, 0xFF);
+        assert_eq!(serial.read_control(), 0x7F);
+    }
+
+    #[test]
+    fn it_should_not_send_with_external_clock() {
+        let mut serial = Serial::new();
+        serial.write_control(0x80);
+
+        for _ in 0..TRANSFER_CYCLES {
+            assert!(!serial.tick());
+        }
+
+        assert!(serial.take_output().is_empty());
+    }
+}
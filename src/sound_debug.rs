@@ -0,0 +1,52 @@
+use crate::keyboard::{Action, KeyBindings};
+use game_boy::apu::{
+    debug_view::{AudioDebugView, AUDIO_DEBUG_HEIGHT, AUDIO_DEBUG_WIDTH},
+    Apu,
+};
+use minifb::{Key, Window};
+
+pub struct AudioWindow {
+    window: Window,
+    view: AudioDebugView,
+}
+
+impl AudioWindow {
+    pub fn new(window: Window) -> AudioWindow {
+        AudioWindow {
+            window,
+            view: AudioDebugView::new(),
+        }
+    }
+
+    pub fn push(&mut self, channel_samples: &[[f32; 4]]) {
+        self.view.push(channel_samples);
+    }
+
+    pub fn render(&mut self, apu: &Apu) {
+        self.window
+            .update_with_buffer(self.view.render(apu), AUDIO_DEBUG_WIDTH, AUDIO_DEBUG_HEIGHT)
+            .unwrap();
+    }
+}
+
+// The mute keys mute a channel, with shift they solo it.
+pub fn handle_channel_keys(window: &Window, keys: &KeyBindings, apu: &mut Apu) {
+    let solo = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+    for channel in 0..4 {
+        if !keys.is_pressed(window, Action::Mute(channel as u8 + 1)) {
+            continue;
+        }
+        if solo {
+            apu.toggle_solo(channel);
+        } else {
+            apu.toggle_mute(channel);
+        }
+        let state: Vec<String> = (0..4)
+            .map(|channel| match apu.is_muted(channel) {
+                true => String::from("-"),
+                false => (channel + 1).to_string(),
+            })
+            .collect();
+        println!("Channels: {}", state.join(" "));
+    }
+}
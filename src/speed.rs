@@ -0,0 +1,129 @@
+#![allow(clippy::new_without_default)]
+
+use crate::keyboard::{Action, KeyBindings};
+use minifb::Window;
+
+pub const SLOW_MOTION_SPEEDS: [f64; 4] = [1.0, 0.5, 0.25, 0.1];
+
+pub struct SpeedControl {
+    fast_forward_speed: Option<f64>,
+    fast_forward_held: bool,
+    fast_forward_toggled: bool,
+    slow_motion: usize,
+    paused: bool,
+    frame_advance: bool,
+}
+
+impl SpeedControl {
+    pub fn new(fast_forward_speed: Option<f64>) -> SpeedControl {
+        SpeedControl {
+            fast_forward_speed,
+            fast_forward_held: false,
+            fast_forward_toggled: false,
+            slow_motion: 0,
+            paused: false,
+            frame_advance: false,
+        }
+    }
+
+    pub fn handle_keys(&mut self, window: &Window, keys: &KeyBindings) {
+        self.fast_forward_held = keys.is_down(window, Action::FastForward);
+        if keys.is_pressed(window, Action::FastForwardToggle) {
+            self.fast_forward_toggled = !self.fast_forward_toggled;
+        }
+        if keys.is_pressed(window, Action::SlowMotion) {
+            self.next_slow_motion();
+        }
+        if keys.is_pressed(window, Action::Pause) {
+            self.toggle_pause();
+        }
+        if keys.is_repeated(window, Action::FrameAdvance) {
+            self.advance_frame();
+        }
+    }
+
+    pub fn next_slow_motion(&mut self) {
+        self.slow_motion = (self.slow_motion + 1) % SLOW_MOTION_SPEEDS.len();
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.frame_advance = false;
+    }
+
+    pub fn advance_frame(&mut self) {
+        if self.paused {
+            self.frame_advance = true;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn is_fast_forward(&self) -> bool {
+        !self.paused && (self.fast_forward_held || self.fast_forward_toggled)
+    }
+
+    pub fn speed(&self) -> Option<f64> {
+        if self.is_fast_forward() {
+            self.fast_forward_speed
+        } else {
+            Some(SLOW_MOTION_SPEEDS[self.slow_motion])
+        }
+    }
+
+    pub fn should_run_frame(&mut self) -> bool {
+        if !self.paused {
+            return true;
+        }
+        let retval = self.frame_advance;
+        self.frame_advance = false;
+        retval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_run_one_frame_on_advance() {
+        let mut speed = SpeedControl::new(None);
+        speed.toggle_pause();
+
+        assert!(!speed.should_run_frame());
+        speed.advance_frame();
+        assert!(speed.should_run_frame());
+        assert!(!speed.should_run_frame());
+    }
+
+    #[test]
+    fn it_should_ignore_advance_when_running() {
+        let mut speed = SpeedControl::new(None);
+        speed.advance_frame();
+        speed.toggle_pause();
+
+        assert!(!speed.should_run_frame());
+    }
+
+    #[test]
+    fn it_should_cycle_slow_motion() {
+        let mut speed = SpeedControl::new(None);
+
+        speed.next_slow_motion();
+        assert_eq!(speed.speed(), Some(0.5));
+        for _ in 1..SLOW_MOTION_SPEEDS.len() {
+            speed.next_slow_motion();
+        }
+        assert_eq!(speed.speed(), Some(1.0));
+    }
+
+    #[test]
+    fn it_should_use_fast_forward_speed() {
+        let mut speed = SpeedControl::new(Some(4.0));
+        speed.fast_forward_toggled = true;
+
+        assert_eq!(speed.speed(), Some(4.0));
+    }
+}
@@ -0,0 +1,122 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+const HEADER_SIZE: u32 = 44;
+const BITS_PER_SAMPLE: u16 = 16;
+// The RIFF size counts the header after its first 8 bytes on 32 bits.
+const MAX_DATA_SIZE: u32 = u32::MAX - (HEADER_SIZE - 8);
+
+// Writes 16-bit PCM samples as they come, the sizes of the header are
+// filled in by finish.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    data_size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, channels: u16, sample_rate: u32) -> io::Result<WavWriter<W>> {
+        writer.write_all(&get_header(channels, sample_rate, 0))?;
+        Ok(WavWriter {
+            writer,
+            data_size: 0,
+        })
+    }
+
+    // Samples go from -1.0 to 1.0, interleaved when there are several channels.
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let data: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| to_pcm(*sample).to_le_bytes())
+            .collect();
+        let data_size = u32::try_from(data.len())
+            .ok()
+            .and_then(|len| self.data_size.checked_add(len))
+            .filter(|size| *size <= MAX_DATA_SIZE)
+            .ok_or_else(|| io::Error::other("the WAV file is full at 4 GiB"))?;
+        self.writer.write_all(&data)?;
+        self.data_size = data_size;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn get_header(channels: u16, sample_rate: u32, data_size: u32) -> Vec<u8> {
+    let block_align = channels * BITS_PER_SAMPLE / 8;
+    let mut retval = vec![];
+    retval.extend_from_slice(b"RIFF");
+    retval.extend_from_slice(&(HEADER_SIZE - 8 + data_size).to_le_bytes());
+    retval.extend_from_slice(b"WAVEfmt ");
+    retval.extend_from_slice(&16u32.to_le_bytes());
+    retval.extend_from_slice(&1u16.to_le_bytes());
+    retval.extend_from_slice(&channels.to_le_bytes());
+    retval.extend_from_slice(&sample_rate.to_le_bytes());
+    retval.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    retval.extend_from_slice(&block_align.to_le_bytes());
+    retval.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+    retval.extend_from_slice(b"data");
+    retval.extend_from_slice(&data_size.to_le_bytes());
+    retval
+}
+
+fn to_pcm(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn it_should_write_header() {
+        let writer = WavWriter::new(Cursor::new(vec![]), 2, 44_100).unwrap();
+
+        let wav = writer.finish().unwrap().into_inner();
+
+        assert_eq!(wav.len(), HEADER_SIZE as usize);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[4..8], &36u32.to_le_bytes());
+        assert_eq!(&wav[22..24], &2u16.to_le_bytes());
+        assert_eq!(&wav[24..28], &44_100u32.to_le_bytes());
+        assert_eq!(&wav[28..32], &176_400u32.to_le_bytes());
+        assert_eq!(&wav[32..34], &4u16.to_le_bytes());
+    }
+
+    #[test]
+    fn it_should_write_samples_and_sizes() {
+        let mut writer = WavWriter::new(Cursor::new(vec![]), 1, 8_000).unwrap();
+
+        writer.write_samples(&[0.0, 1.0]).unwrap();
+        writer.write_samples(&[-1.0, 2.0]).unwrap();
+        let wav = writer.finish().unwrap().into_inner();
+
+        assert_eq!(&wav[4..8], &44u32.to_le_bytes());
+        assert_eq!(&wav[40..44], &8u32.to_le_bytes());
+        assert_eq!(
+            &wav[44..],
+            &[0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80, 0xFF, 0x7F]
+        );
+    }
+
+    #[test]
+    fn it_should_stop_at_the_size_limit() {
+        let mut writer = WavWriter::new(Cursor::new(vec![]), 1, 8_000).unwrap();
+        writer.data_size = MAX_DATA_SIZE - 2;
+
+        writer.write_samples(&[0.5]).unwrap();
+        assert!(writer.write_samples(&[0.5]).is_err());
+        let wav = writer.finish().unwrap().into_inner();
+
+        assert_eq!(&wav[4..8], &u32::MAX.to_le_bytes());
+        assert_eq!(wav.len(), HEADER_SIZE as usize + 2);
+    }
+}